@@ -0,0 +1,146 @@
+// Compares the parallel scanner against the previous single-threaded walk.
+//
+//   cargo bench --bench scan               # generated synthetic tree
+//   cargo bench --bench scan -- <path>     # an existing directory
+
+#[allow(dead_code, unused_imports)]
+#[path = "../src/dir.rs"]
+mod dir;
+#[allow(dead_code)]
+#[path = "../src/tree.rs"]
+mod tree;
+
+use std::{
+    fs,
+    io::Error,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tree::Tree;
+
+const FANOUT: usize = 8;
+const DEPTH: usize = 4;
+const FILES_PER_DIR: usize = 32;
+const ROUNDS: usize = 3;
+
+// The walker dir.rs shipped with before the scan was parallelised.
+fn serial_walk(
+    path: &Path,
+    cb: &mut impl FnMut(&Path, &Path, bool, u64) -> Result<bool, Error>,
+) -> Result<(u64, bool), Error> {
+    let mut total: u64 = 0;
+
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let is_dir = metadata.is_dir();
+        let size = if is_dir {
+            let result = serial_walk(entry.path().as_path(), cb)?;
+            if !result.1 {
+                return Ok((total, false));
+            }
+            result.0
+        } else {
+            metadata.len()
+        };
+        total += size;
+
+        if !cb(path, &entry.path(), is_dir, size)? {
+            return Ok((total, false));
+        }
+    }
+
+    Ok((total, true))
+}
+
+fn generate(path: &Path, depth: usize) -> Result<(), Error> {
+    fs::create_dir_all(path)?;
+    for i in 0..FILES_PER_DIR {
+        // Sparse files, so the tree costs inodes but (almost) no disk space.
+        let file = fs::File::create(path.join(format!("file{}.bin", i)))?;
+        file.set_len((i as u64 + 1) * 4096)?;
+    }
+    if depth > 0 {
+        for i in 0..FANOUT {
+            generate(&path.join(format!("dir{}", i)), depth - 1)?;
+        }
+    }
+    Ok(())
+}
+
+fn synthetic_tree() -> PathBuf {
+    let root = std::env::temp_dir().join(format!(
+        "rustitude-bench-{}x{}x{}",
+        FANOUT, DEPTH, FILES_PER_DIR
+    ));
+    let marker = root.join(".complete");
+    if !marker.exists() {
+        println!("generating synthetic tree in {}.", root.display());
+        let _ = fs::remove_dir_all(&root);
+        generate(&root, DEPTH).expect("failed to generate synthetic tree");
+        fs::File::create(&marker).unwrap();
+    }
+    root
+}
+
+fn measure(name: &str, mut f: impl FnMut() -> (u64, u64)) {
+    let mut best = Duration::from_secs(u64::MAX);
+    let mut result = (0, 0);
+    for _ in 0..ROUNDS {
+        let now = Instant::now();
+        result = f();
+        best = best.min(now.elapsed());
+    }
+    println!(
+        "{:<12} {:>8} ms  ({} entries, {} bytes)",
+        name,
+        best.as_millis(),
+        result.1,
+        result.0
+    );
+}
+
+fn main() {
+    let root = match std::env::args().skip(1).find(|arg| !arg.starts_with('-')) {
+        Some(path) => PathBuf::from(path),
+        None => synthetic_tree(),
+    };
+    println!("scanning {}.", root.display());
+
+    measure("serial", || {
+        let mut count = 0;
+        let (total, _) = serial_walk(&root, &mut |_, _, _, _| {
+            count += 1;
+            Ok(true)
+        })
+        .unwrap();
+        (total, count)
+    });
+
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let mut threads = 1;
+    while threads <= cores {
+        measure(&format!("parallel/{}", threads), || {
+            let tree = Mutex::new(Tree::new(&root));
+            let options = dir::ScanOptions {
+                threads,
+                ..dir::ScanOptions::default()
+            };
+            let mut hard_links = dir::HardLinks::default();
+            let summary = dir::scan(
+                &tree,
+                Tree::ROOT,
+                &root,
+                &options,
+                &mut hard_links,
+                &mut |_| true,
+            )
+            .unwrap();
+            // The root is not an entry of its own.
+            let count = tree.into_inner().unwrap().len() as u64 - 1;
+            (summary.total.apparent, count)
+        });
+        threads *= 2;
+    }
+}
@@ -0,0 +1,206 @@
+use crate::tree::{Kind, NodeId, SizeMode, Tree};
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fs,
+    io::{self, Error},
+    path::Path,
+};
+
+pub type CategoryId = u8;
+
+// One category per line: its name, its color and the extensions it holds.
+// Lines starting with # are comments.
+const DEFAULT: &str = "\
+# name color extensions...
+video #e15759 mp4 mkv avi mov webm wmv flv m4v mpg mpeg ts
+audio #f28e2b mp3 flac wav ogg opus m4a aac wma
+images #edc948 jpg jpeg png gif bmp tif tiff webp heic svg psd raw cr2 nef dng
+archives #b07aa1 zip tar gz tgz bz2 xz zst 7z rar lz4 deb rpm
+disk-images #9c755f iso img dmg vhd vhdx vmdk qcow2 vdi
+documents #76b7b2 pdf doc docx xls xlsx ppt pptx odt ods txt md epub
+source #59a14f rs c h cc cpp hpp py js ts jsx tsx go java kt rb php cs swift sh html css
+binaries #ff9da7 exe dll so dylib a o obj lib bin class jar wasm rlib pdb
+databases #4e79a7 db sqlite sqlite3 mdb ldb sst wal
+";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Category {
+    pub name: String,
+    pub color: (u8, u8, u8),
+    pub extensions: Vec<String>,
+}
+
+/// Maps the extensions of the files to categories, for coloring the chart.
+#[derive(Clone, Debug)]
+pub struct Categories {
+    categories: Vec<Category>,
+    by_extension: HashMap<String, CategoryId>,
+}
+
+impl Default for Categories {
+    fn default() -> Self {
+        Categories::parse(DEFAULT).unwrap()
+    }
+}
+
+impl Categories {
+    /// Reads categories in the format of `to_text`.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut categories = Vec::new();
+        let mut by_extension = HashMap::new();
+        for (number, line) in (1..).zip(text.lines()) {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let name = words.next().unwrap();
+            let color = words
+                .next()
+                .and_then(parse_color)
+                .ok_or_else(|| format!("line {}: expected a color like #4faaff", number))?;
+            if categories.len() > CategoryId::MAX as usize {
+                return Err(format!("line {}: too many categories", number));
+            }
+            let id = categories.len() as CategoryId;
+            let extensions: Vec<String> = words
+                .map(|extension| extension.trim_start_matches('.').to_lowercase())
+                .collect();
+            for extension in &extensions {
+                // The first category listing an extension keeps it.
+                by_extension.entry(extension.clone()).or_insert(id);
+            }
+            categories.push(Category {
+                name: name.to_string(),
+                color,
+                extensions,
+            });
+        }
+        Ok(Categories {
+            categories,
+            by_extension,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let text = fs::read_to_string(path)?;
+        Categories::parse(&text).map_err(|err| Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::from("# name color extensions...\n");
+        for category in &self.categories {
+            let (r, g, b) = category.color;
+            text += &format!("{} #{:02x}{:02x}{:02x}", category.name, r, g, b);
+            for extension in &category.extensions {
+                text += " ";
+                text += extension;
+            }
+            text += "\n";
+        }
+        text
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Category> {
+        self.categories.iter()
+    }
+
+    pub fn get(&self, id: CategoryId) -> &Category {
+        &self.categories[id as usize]
+    }
+
+    /// The category of a file named `name`, by its extension.
+    pub fn of(&self, name: &OsStr) -> Option<CategoryId> {
+        let extension = Path::new(name).extension()?.to_str()?.to_lowercase();
+        self.by_extension.get(&extension).copied()
+    }
+
+    /// The category of every node, indexed by id. Directories take the one
+    /// that holds most of their size.
+    pub fn classify(&self, tree: &Tree, mode: SizeMode) -> Vec<Option<CategoryId>> {
+        let mut result = vec![None; tree.len()];
+        // Size by category below the directories, indexed by id and filled in
+        // by their children which always come after them.
+        let mut sizes: Vec<Option<Box<[u64]>>> = vec![None; tree.len()];
+        let empty = || vec![0; self.categories.len()].into_boxed_slice();
+        for id in (0..tree.len() as NodeId).rev() {
+            let parent = tree.parent(id);
+            let category = if let Some(own) = sizes[id as usize].take() {
+                let dominant = (0..own.len())
+                    .filter(|i| own[*i] > 0)
+                    .max_by_key(|i| own[*i])
+                    .map(|i| i as CategoryId);
+                if let Some(parent) = parent {
+                    let total = sizes[parent as usize].get_or_insert_with(empty);
+                    for (total, size) in total.iter_mut().zip(own.iter()) {
+                        *total += size;
+                    }
+                }
+                dominant
+            } else if tree.kind(id) == Kind::File || tree.kind(id) == Kind::Symlink {
+                let category = self.of(tree.name(id));
+                if let (Some(category), Some(parent)) = (category, parent) {
+                    sizes[parent as usize].get_or_insert_with(empty)[category as usize] +=
+                        tree.size(id).get(mode);
+                }
+                category
+            } else {
+                None
+            };
+            result[id as usize] = category;
+        }
+        result
+    }
+}
+
+// `#rrggbb`
+fn parse_color(text: &str) -> Option<(u8, u8, u8)> {
+    let hex = text.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    Some(((value >> 16) as u8, (value >> 8) as u8, value as u8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::Size;
+
+    #[test]
+    fn classifies_by_extension() {
+        let categories =
+            Categories::parse("# comment\nvideo #ff0000 mp4 .MKV\nsource #00ff00 rs\n").unwrap();
+        assert_eq!(categories.of(OsStr::new("a.Mkv")), Some(0));
+        assert_eq!(categories.of(OsStr::new("README")), None);
+        assert_eq!(categories.get(1).color, (0, 0xff, 0));
+        assert!(Categories::parse("video red mp4").is_err());
+        assert_eq!(
+            Categories::parse(&categories.to_text())
+                .unwrap()
+                .get(0)
+                .extensions,
+            vec!["mp4", "mkv"]
+        );
+
+        let mut tree = Tree::new(Path::new("/data"));
+        let src = tree.add(Tree::ROOT, OsStr::new("src"), Kind::Dir, Size::default(), 0);
+        for (parent, name, size) in [
+            (src, "main.rs", 10),
+            (src, "clip.mp4", 5),
+            (Tree::ROOT, "movie.mkv", 100),
+        ] {
+            let size = Size {
+                apparent: size,
+                allocated: size,
+            };
+            tree.add(parent, OsStr::new(name), Kind::File, size, 0);
+            tree.grow(parent, size);
+        }
+        let classified = categories.classify(&tree, SizeMode::Apparent);
+        assert_eq!(classified[src as usize], Some(1));
+        assert_eq!(classified[Tree::ROOT as usize], Some(0));
+    }
+}
@@ -0,0 +1,525 @@
+use crate::{
+    category::Categories,
+    diff::{Change, Diff},
+    dir, format_delta, format_size, ncdu, snapshot,
+    tree::{Kind, NodeId, SizeMode, Tree},
+};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use normpath::PathExt;
+use serde_json::{json, Value};
+use std::{
+    cmp::Reverse,
+    ffi::OsStr,
+    io::Error,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Instant,
+};
+
+/// Arguments of the scanner, shared by the subcommands.
+fn scan_option_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("one-file-system")
+            .short("x")
+            .long("one-file-system")
+            .help("Stays on the file system of the scanned directory"),
+        Arg::with_name("exclude")
+            .long("exclude")
+            .value_name("PATTERN")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Skips the paths matching a gitignore-style pattern"),
+        Arg::with_name("count-excluded")
+            .long("count-excluded")
+            .help("Adds up the size of the excluded paths"),
+        Arg::with_name("no-dedup-hard-links")
+            .long("no-dedup-hard-links")
+            .help("Counts a file with several hard links under each of its paths"),
+        Arg::with_name("follow-symlinks")
+            .long("follow-symlinks")
+            .value_name("POLICY")
+            .possible_values(&["never", "within-root", "always"])
+            .default_value("never")
+            .help("Which symlinks to directories outside the root to walk into"),
+        Arg::with_name("threads")
+            .long("threads")
+            .value_name("N")
+            .default_value("0")
+            .validator(is_number)
+            .help("Number of scanner threads, 0 for one per core"),
+    ]
+}
+
+/// How much of the tree is shown, by the chart or the printed tree.
+fn view_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("depth")
+            .long("depth")
+            .value_name("N")
+            .default_value("10")
+            .validator(is_number)
+            .help("Levels of directories to show"),
+        Arg::with_name("top")
+            .long("top")
+            .value_name("N")
+            .default_value("20")
+            .validator(is_number)
+            .help("Largest entries to show per directory"),
+    ]
+}
+
+/// How the subcommands print their results.
+fn output_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("allocated")
+            .long("allocated")
+            .help("Uses the size allocated on disk instead of the apparent size"),
+        Arg::with_name("json")
+            .long("json")
+            .help("Prints the result as JSON"),
+    ]
+}
+
+pub fn app<'a, 'b>() -> App<'a, 'b> {
+    App::new("rustitude")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Shows what takes up the space in a directory")
+        .arg(Arg::with_name("path").help(
+            "Directory, snapshot or ncdu dump to show, a dialog asks for one when it is missing",
+        ))
+        .args(&view_args())
+        .arg(
+            Arg::with_name("rings")
+                .long("rings")
+                .value_name("N")
+                .default_value("8")
+                .validator(is_number)
+                .help("Rings of the chart to show at most"),
+        )
+        .arg(
+            Arg::with_name("min-segment")
+                .long("min-segment")
+                .value_name("DEGREES")
+                .default_value("0.6")
+                .validator(|value| match value.parse::<f64>() {
+                    Ok(angle) if (0.0..=360.0).contains(&angle) => Ok(()),
+                    _ => Err(format!("{} is not an angle in degrees", value)),
+                })
+                .help("Smallest segment of the chart, the smaller entries are grouped"),
+        )
+        .arg(
+            Arg::with_name("categories")
+                .long("categories")
+                .value_name("FILE")
+                .help("Reads the file categories of the chart from a file, see the categories command"),
+        )
+        .args(&scan_option_args())
+        .subcommand(scan_subcommand())
+        .subcommand(diff_subcommand())
+        .subcommand(
+            SubCommand::with_name("categories")
+                .about("Prints the default file categories, to edit and pass to --categories"),
+        )
+}
+
+fn scan_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("scan")
+        .about("Scans a directory and prints the largest entries")
+        .arg(
+            Arg::with_name("path")
+                .required(true)
+                .help("Directory to scan, or a snapshot or ncdu dump to read instead"),
+        )
+        .args(&view_args())
+        .args(&output_args())
+        .arg(
+            Arg::with_name("save")
+                .long("save")
+                .value_name("FILE")
+                .help("Also saves the whole scan as a snapshot"),
+        )
+        .arg(
+            Arg::with_name("save-ncdu")
+                .long("save-ncdu")
+                .value_name("FILE")
+                .help("Also saves the whole scan in the JSON format of ncdu"),
+        )
+        .args(&scan_option_args())
+}
+
+fn diff_subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("diff")
+        .about("Compares two scans and prints the paths that grew the most")
+        .arg(
+            Arg::with_name("old")
+                .required(true)
+                .help("Snapshot or directory to compare against"),
+        )
+        .arg(
+            Arg::with_name("new")
+                .required(true)
+                .help("Snapshot or directory to compare"),
+        )
+        .arg(
+            Arg::with_name("top")
+                .long("top")
+                .value_name("N")
+                .default_value("20")
+                .validator(is_number)
+                .help("Number of paths to list"),
+        )
+        .args(&output_args())
+        .args(&scan_option_args())
+}
+
+fn is_number(value: String) -> Result<(), String> {
+    value
+        .parse::<usize>()
+        .map(|_| ())
+        .map_err(|_| format!("{} is not a number", value))
+}
+
+/// The absolute path of the directory or snapshot given on the command line,
+/// or why it cannot be opened.
+pub fn source(path: &OsStr) -> Result<PathBuf, String> {
+    let path = Path::new(path);
+    let normalized = path
+        .normalize()
+        .map_err(|err| format!("{}: {}", path.display(), err))?;
+    if !normalized.is_dir() && !normalized.is_file() {
+        return Err(format!("{}: not a directory or a snapshot", path.display()));
+    }
+    Ok(normalized.into_path_buf())
+}
+
+pub fn depth(matches: &ArgMatches) -> usize {
+    matches.value_of("depth").unwrap().parse().unwrap()
+}
+
+/// The categories given with --categories, or the default ones.
+pub fn categories(matches: &ArgMatches) -> Result<Categories, String> {
+    match matches.value_of_os("categories") {
+        Some(path) => {
+            let path = Path::new(path);
+            Categories::load(path).map_err(|err| format!("{}: {}", path.display(), err))
+        }
+        None => Ok(Categories::default()),
+    }
+}
+
+pub fn print_categories() -> i32 {
+    print!("{}", Categories::default().to_text());
+    0
+}
+
+pub fn min_segment(matches: &ArgMatches) -> f64 {
+    matches.value_of("min-segment").unwrap().parse().unwrap()
+}
+
+pub fn rings(matches: &ArgMatches) -> usize {
+    matches.value_of("rings").unwrap().parse().unwrap()
+}
+
+pub fn top(matches: &ArgMatches) -> usize {
+    matches.value_of("top").unwrap().parse().unwrap()
+}
+
+pub fn scan_options(matches: &ArgMatches) -> dir::ScanOptions {
+    dir::ScanOptions {
+        threads: matches.value_of("threads").unwrap().parse().unwrap(),
+        symlinks: match matches.value_of("follow-symlinks") {
+            Some("within-root") => dir::SymlinkPolicy::WithinRoot,
+            Some("always") => dir::SymlinkPolicy::Always,
+            _ => dir::SymlinkPolicy::Never,
+        },
+        one_file_system: matches.is_present("one-file-system"),
+        excludes: matches
+            .values_of("exclude")
+            .map_or(Vec::new(), |values| values.map(String::from).collect()),
+        count_excluded: matches.is_present("count-excluded"),
+        dedup_hard_links: !matches.is_present("no-dedup-hard-links"),
+    }
+}
+
+fn size_mode(matches: &ArgMatches) -> SizeMode {
+    if matches.is_present("allocated") {
+        SizeMode::Allocated
+    } else {
+        SizeMode::Apparent
+    }
+}
+
+fn mode_name(mode: SizeMode) -> &'static str {
+    match mode {
+        SizeMode::Apparent => "apparent",
+        SizeMode::Allocated => "allocated",
+    }
+}
+
+fn kind_name(kind: Kind) -> &'static str {
+    match kind {
+        Kind::Dir => "dir",
+        Kind::File => "file",
+        Kind::Symlink => "symlink",
+        Kind::MountPoint => "mountpoint",
+        Kind::Excluded => "excluded",
+    }
+}
+
+// The largest `top` children, largest first.
+fn largest(tree: &Tree, id: NodeId, mode: SizeMode, top: usize) -> Vec<NodeId> {
+    let mut children: Vec<NodeId> = tree.children(id).collect();
+    children.sort_by_key(|child| Reverse(tree.size(*child).get(mode)));
+    children.truncate(top);
+    children
+}
+
+fn print_tree(tree: &Tree, id: NodeId, mode: SizeMode, top: usize, depth: usize, level: usize) {
+    let name = if id == Tree::ROOT {
+        tree.path(id)
+    } else {
+        PathBuf::from(tree.name(id))
+    };
+    let suffix = match tree.kind(id) {
+        Kind::Dir | Kind::MountPoint => "/",
+        Kind::Symlink => "@",
+        _ => "",
+    };
+    println!(
+        "{:>12}  {}{}{}",
+        format_size(tree.size(id).get(mode)),
+        "  ".repeat(level),
+        name.display(),
+        suffix
+    );
+    if level < depth {
+        for child in largest(tree, id, mode, top) {
+            print_tree(tree, child, mode, top, depth, level + 1);
+        }
+    }
+}
+
+fn json_tree(tree: &Tree, id: NodeId, mode: SizeMode, top: usize, depth: usize) -> Value {
+    let size = tree.size(id);
+    let mut value = json!({
+        "name": tree.name(id).to_string_lossy(),
+        "kind": kind_name(tree.kind(id)),
+        "apparent": size.apparent,
+        "allocated": size.allocated,
+    });
+    if depth > 0 && tree.children(id).next().is_some() {
+        value["children"] = largest(tree, id, mode, top)
+            .into_iter()
+            .map(|child| json_tree(tree, child, mode, top, depth - 1))
+            .collect();
+    }
+    value
+}
+
+fn scan_directory(
+    path: &Path,
+    options: &dir::ScanOptions,
+) -> Result<(Tree, snapshot::Info), Error> {
+    let tree = Mutex::new(Tree::new(path));
+    let scanned_at = snapshot::now();
+    let summary = dir::scan(
+        &tree,
+        Tree::ROOT,
+        path,
+        options,
+        &mut dir::HardLinks::default(),
+        &mut |_| true,
+    )?;
+    let info = snapshot::Info {
+        scanned_at,
+        options: options.clone(),
+        errors: summary.errors,
+        deduplicated: summary.deduplicated,
+    };
+    Ok((tree.into_inner().unwrap(), info))
+}
+
+// Scans a directory, or reads the snapshot at `path`.
+fn read(path: &Path, options: &dir::ScanOptions) -> Result<(Tree, snapshot::Info), Error> {
+    if path.is_file() {
+        snapshot::open(path)
+    } else {
+        scan_directory(path, options)
+    }
+}
+
+/// Runs `rustitude scan` and returns the exit code.
+pub fn scan(matches: &ArgMatches) -> i32 {
+    let path = match source(matches.value_of_os("path").unwrap()) {
+        Ok(path) => path,
+        Err(err) => {
+            eprintln!("rustitude: {}", err);
+            return 1;
+        }
+    };
+    let path = path.as_path();
+    let mode = size_mode(matches);
+    let depth = depth(matches);
+    let top = top(matches);
+    let options = scan_options(matches);
+
+    let now = Instant::now();
+    let (tree, info) = match read(path, &options) {
+        Ok(result) => result,
+        Err(err) => {
+            eprintln!("rustitude: {}: {}", path.display(), err);
+            return 1;
+        }
+    };
+    let elapsed = now.elapsed();
+
+    if let Some(file) = matches.value_of_os("save") {
+        if let Err(err) = snapshot::save(Path::new(file), &tree, &info) {
+            eprintln!("rustitude: {}: {}", Path::new(file).display(), err);
+            return 1;
+        }
+    }
+    if let Some(file) = matches.value_of_os("save-ncdu") {
+        if let Err(err) = ncdu::save(Path::new(file), &tree, &info) {
+            eprintln!("rustitude: {}: {}", Path::new(file).display(), err);
+            return 1;
+        }
+    }
+
+    if matches.is_present("json") {
+        let errors: Vec<Value> = info
+            .errors
+            .iter()
+            .map(|error| {
+                json!({
+                    "path": error.path.to_string_lossy(),
+                    "kind": error.kind.to_string(),
+                    "message": error.message,
+                })
+            })
+            .collect();
+        let output = json!({
+            "root": tree.path(Tree::ROOT).to_string_lossy(),
+            "scanned_at": info.scanned_at,
+            "size_mode": mode_name(mode),
+            "elapsed_ms": elapsed.as_millis() as u64,
+            "deduplicated": info.deduplicated.get(mode),
+            "excluded": tree
+                .children(Tree::ROOT)
+                .filter(|child| tree.kind(*child) == Kind::Excluded)
+                .map(|child| tree.size(child).get(mode))
+                .sum::<u64>(),
+            "errors": errors,
+            "tree": json_tree(&tree, Tree::ROOT, mode, top, depth),
+        });
+        println!("{}", output);
+    } else {
+        print_tree(&tree, Tree::ROOT, mode, top, depth, 0);
+        for error in &info.errors {
+            eprintln!(
+                "rustitude: skipped {}: {} ({})",
+                error.path.display(),
+                error.kind,
+                error.message
+            );
+        }
+    }
+    0
+}
+
+fn status_name(change: &Change) -> &'static str {
+    if change.is_added() {
+        "added"
+    } else if change.is_deleted() {
+        "deleted"
+    } else {
+        "changed"
+    }
+}
+
+/// Runs `rustitude diff` and returns the exit code.
+pub fn diff(matches: &ArgMatches) -> i32 {
+    let mode = size_mode(matches);
+    let top = top(matches);
+    let options = scan_options(matches);
+
+    let mut scans = Vec::new();
+    for name in &["old", "new"] {
+        let result = source(matches.value_of_os(name).unwrap()).and_then(|path| {
+            read(&path, &options)
+                .map(|(tree, info)| (path.clone(), tree, info))
+                .map_err(|err| format!("{}: {}", path.display(), err))
+        });
+        match result {
+            Ok(scan) => scans.push(scan),
+            Err(err) => {
+                eprintln!("rustitude: {}", err);
+                return 1;
+            }
+        }
+    }
+    let (new_path, new_tree, new_info) = scans.pop().unwrap();
+    let (old_path, old_tree, old_info) = scans.pop().unwrap();
+
+    let diff = Diff::new(&old_tree, &new_tree);
+    let total = diff.change(Tree::ROOT);
+    let growers = diff.growers(mode, top);
+
+    if matches.is_present("json") {
+        let growers: Vec<Value> = growers
+            .into_iter()
+            .map(|id| {
+                let change = diff.change(id);
+                json!({
+                    "path": diff.tree.path(id).to_string_lossy(),
+                    "kind": kind_name(diff.tree.kind(id)),
+                    "status": status_name(&change),
+                    "old": change.old_size(mode),
+                    "new": change.new_size(mode),
+                    "delta": change.delta(mode),
+                })
+            })
+            .collect();
+        let output = json!({
+            "root": diff.tree.path(Tree::ROOT).to_string_lossy(),
+            "old": {
+                "path": old_path.to_string_lossy(),
+                "scanned_at": old_info.scanned_at,
+            },
+            "new": {
+                "path": new_path.to_string_lossy(),
+                "scanned_at": new_info.scanned_at,
+            },
+            "size_mode": mode_name(mode),
+            "old_size": total.old_size(mode),
+            "new_size": total.new_size(mode),
+            "delta": total.delta(mode),
+            "growers": growers,
+        });
+        println!("{}", output);
+    } else {
+        println!(
+            "{:>12}  {} ({} to {})",
+            format_delta(total.delta(mode)),
+            diff.tree.path(Tree::ROOT).display(),
+            format_size(total.old_size(mode)),
+            format_size(total.new_size(mode))
+        );
+        for id in growers {
+            let change = diff.change(id);
+            let suffix = match diff.tree.kind(id) {
+                Kind::Dir | Kind::MountPoint => "/",
+                Kind::Symlink => "@",
+                _ => "",
+            };
+            println!(
+                "{:>12}  {}{}{}",
+                format_delta(change.delta(mode)),
+                diff.tree.path(id).display(),
+                suffix,
+                if change.is_added() { " (added)" } else { "" }
+            );
+        }
+    }
+    0
+}
@@ -0,0 +1,217 @@
+use crate::tree::{NodeId, Size, SizeMode, Tree};
+use std::{cmp::Reverse, collections::HashMap, ffi::OsStr};
+
+/// Sizes of a path in the two scans, `None` where it did not exist.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Change {
+    pub old: Option<Size>,
+    pub new: Option<Size>,
+}
+
+impl Change {
+    pub fn is_added(&self) -> bool {
+        self.old.is_none()
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.new.is_none()
+    }
+
+    pub fn old_size(&self, mode: SizeMode) -> u64 {
+        self.old.map_or(0, |size| size.get(mode))
+    }
+
+    pub fn new_size(&self, mode: SizeMode) -> u64 {
+        self.new.map_or(0, |size| size.get(mode))
+    }
+
+    pub fn delta(&self, mode: SizeMode) -> i64 {
+        self.new_size(mode) as i64 - self.old_size(mode) as i64
+    }
+}
+
+/// Two scans of a directory merged by path.
+///
+/// The tree holds the paths of both scans. Its sizes add up the larger of the
+/// two sizes of every leaf, so that deleted paths still take up room in the
+/// chart. The sizes of the scans are in `change`.
+pub struct Diff {
+    pub tree: Tree,
+    changes: Vec<Change>,
+}
+
+impl Diff {
+    /// Compares `new` against `old`, whatever their roots are named.
+    pub fn new(old: &Tree, new: &Tree) -> Self {
+        let mut diff = Diff {
+            tree: Tree::new(&new.path(Tree::ROOT)),
+            changes: vec![Change {
+                old: Some(old.size(Tree::ROOT)),
+                new: Some(new.size(Tree::ROOT)),
+            }],
+        };
+        diff.tree.set_mtime(Tree::ROOT, new.mtime(Tree::ROOT));
+        diff.tree.set_atime(Tree::ROOT, new.atime(Tree::ROOT));
+        diff.merge(Tree::ROOT, Some((old, Tree::ROOT)), Some((new, Tree::ROOT)));
+        diff
+    }
+
+    pub fn change(&self, id: NodeId) -> Change {
+        self.changes[id as usize]
+    }
+
+    // Adds the children of the `old` and `new` nodes under `id`, matched by name.
+    fn merge(&mut self, id: NodeId, old: Option<(&Tree, NodeId)>, new: Option<(&Tree, NodeId)>) {
+        let mut old_children: HashMap<&OsStr, NodeId> = match old {
+            Some((tree, old_id)) => tree
+                .children(old_id)
+                .map(|child| (tree.name(child), child))
+                .collect(),
+            None => HashMap::new(),
+        };
+
+        let mut leaf = true;
+        if let Some((new_tree, new_id)) = new {
+            for new_child in new_tree.children(new_id) {
+                let name = new_tree.name(new_child);
+                let old_child = old
+                    .zip(old_children.remove(name))
+                    .map(|((old_tree, _), child)| (old_tree, child));
+                self.add(id, name, old_child, Some((new_tree, new_child)));
+                leaf = false;
+            }
+        }
+        if let Some((old_tree, _)) = old {
+            let mut deleted: Vec<NodeId> = old_children.into_values().collect();
+            deleted.sort_unstable();
+            for old_child in deleted {
+                self.add(
+                    id,
+                    old_tree.name(old_child),
+                    Some((old_tree, old_child)),
+                    None,
+                );
+                leaf = false;
+            }
+        }
+
+        if leaf {
+            let change = self.changes[id as usize];
+            let old = change.old.unwrap_or_default();
+            let new = change.new.unwrap_or_default();
+            let size = Size {
+                apparent: old.apparent.max(new.apparent),
+                allocated: old.allocated.max(new.allocated),
+            };
+            self.tree.grow(id, size);
+        }
+    }
+
+    fn add(
+        &mut self,
+        parent: NodeId,
+        name: &OsStr,
+        old: Option<(&Tree, NodeId)>,
+        new: Option<(&Tree, NodeId)>,
+    ) {
+        // The newer kind wins when a file became a directory or the reverse.
+        let (tree, source) = new.or(old).unwrap();
+        let id = self.tree.add(
+            parent,
+            name,
+            tree.kind(source),
+            Size::default(),
+            tree.mtime(source),
+        );
+        self.tree.set_atime(id, tree.atime(source));
+        self.changes.push(Change {
+            old: old.map(|(tree, id)| tree.size(id)),
+            new: new.map(|(tree, id)| tree.size(id)),
+        });
+        self.merge(id, old, new);
+    }
+
+    /// The `count` paths that grew the most, largest first. A directory is
+    /// left out when a single child accounts for all of its growth.
+    pub fn growers(&self, mode: SizeMode, count: usize) -> Vec<NodeId> {
+        let tree = &self.tree;
+        let mut growers: Vec<NodeId> = (1..tree.len() as NodeId)
+            .filter(|id| {
+                let delta = self.change(*id).delta(mode);
+                delta > 0
+                    && tree
+                        .children(*id)
+                        .all(|child| self.change(child).delta(mode) != delta)
+            })
+            .collect();
+        growers.sort_by_key(|id| Reverse(self.change(*id).delta(mode)));
+        growers.truncate(count);
+        growers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::Kind;
+    use std::path::Path;
+
+    fn file(tree: &mut Tree, parent: NodeId, name: &str, size: u64) -> NodeId {
+        let size = Size {
+            apparent: size,
+            allocated: size,
+        };
+        let id = tree.add(parent, OsStr::new(name), Kind::File, size, 0);
+        tree.grow(parent, size);
+        id
+    }
+
+    #[test]
+    fn compares_by_path() {
+        let root = Path::new("/data");
+        let mut old = Tree::new(root);
+        let logs = old.add(
+            Tree::ROOT,
+            OsStr::new("logs"),
+            Kind::Dir,
+            Size::default(),
+            0,
+        );
+        file(&mut old, logs, "a.log", 100);
+        file(&mut old, logs, "b.log", 50);
+        file(&mut old, Tree::ROOT, "gone", 30);
+
+        let mut new = Tree::new(root);
+        let logs = new.add(
+            Tree::ROOT,
+            OsStr::new("logs"),
+            Kind::Dir,
+            Size::default(),
+            0,
+        );
+        file(&mut new, logs, "a.log", 400);
+        file(&mut new, logs, "b.log", 50);
+        file(&mut new, Tree::ROOT, "added", 10);
+
+        let diff = Diff::new(&old, &new);
+        let mode = SizeMode::Apparent;
+        let find = |name: &str| {
+            (0..diff.tree.len() as NodeId)
+                .find(|id| diff.tree.name(*id) == OsStr::new(name))
+                .unwrap()
+        };
+
+        assert_eq!(diff.change(Tree::ROOT).delta(mode), 460 - 180);
+        assert_eq!(diff.change(find("a.log")).delta(mode), 300);
+        assert!(diff.change(find("gone")).is_deleted());
+        assert!(diff.change(find("added")).is_added());
+        // Deleted paths still take room, at their old size.
+        assert_eq!(diff.tree.size(Tree::ROOT).apparent, 400 + 50 + 30 + 10);
+        assert_eq!(
+            diff.tree.path(find("a.log")),
+            root.join("logs").join("a.log")
+        );
+        // `logs` only grew through a.log.
+        assert_eq!(diff.growers(mode, 10), vec![find("a.log"), find("added")]);
+    }
+}
@@ -1,49 +1,923 @@
-use std::{fs, io::Error, path::Path};
-
-pub fn get_directory_size_recursive(
-  path: &Path,
-  cb: &mut impl FnMut(&str, &str, bool, u64) -> Result<bool, Error>,
-) -> Result<(u64, bool), Error> {
-  fn get_directory_size_recursive_impl(
-      canceled: &mut bool,
-      path: &Path,
-      cb: &mut impl FnMut(&str, &str, bool, u64) -> Result<bool, Error>,
-  ) -> Result<(u64, bool), Error> {
-      let mut total: u64 = 0;
-
-      let dir = fs::read_dir(path)?;
-      for entry in dir {
-          let entry = entry?;
-          let metadata = entry.metadata()?;
-          let is_dir = metadata.is_dir();
-          let size = if is_dir {
-              let result =
-                  get_directory_size_recursive_impl(canceled, entry.path().as_path(), cb)?;
-              *canceled = !result.1;
-              if *canceled {
-                  return Ok((total, false));
-              } else {
-                  result.0
-              }
-          } else {
-              metadata.len()
-          };
-          total += size;
-
-          *canceled = !cb(
-              path.to_str().unwrap(),
-              entry.path().to_str().unwrap(),
-              is_dir,
-              size,
-          )?;
-          if *canceled {
-              return Ok((total, false));
-          }
-      }
-
-      Ok((total, true))
-  }
-
-  let mut canceled = false;
-  return get_directory_size_recursive_impl(&mut canceled, path, cb);
+use crate::tree::{Kind, NodeId, Size, Tree};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize, Serializer};
+use std::{
+    collections::{hash_map, HashMap, HashSet},
+    ffi::{OsStr, OsString},
+    fmt, fs,
+    io::{self, Error},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{sync_channel, SyncSender},
+        Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// Number of finished directories the workers may queue up before they block on
+// the callback.
+const EVENT_QUEUE_SIZE: usize = 1024;
+
+// (dev, inode)
+type FileId = (u64, u64);
+
+// (mtime, atime)
+type Times = (u32, u32);
+
+/// Exclude patterns read from the root of the tree, in gitignore syntax.
+pub const IGNORE_FILE: &str = ".rustitudeignore";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanErrorKind {
+    PermissionDenied,
+    Vanished,
+    Io,
+    SymlinkLoop,
+}
+
+impl fmt::Display for ScanErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScanErrorKind::PermissionDenied => write!(f, "permission denied"),
+            ScanErrorKind::Vanished => write!(f, "vanished"),
+            ScanErrorKind::Io => write!(f, "I/O error"),
+            ScanErrorKind::SymlinkLoop => write!(f, "symlink loop"),
+        }
+    }
+}
+
+/// A path the scanner had to skip.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScanError {
+    #[serde(serialize_with = "serialize_lossy")]
+    pub path: PathBuf,
+    pub kind: ScanErrorKind,
+    pub message: String,
+}
+
+// Saving a scan should not fail on a name that is not valid UTF-8.
+fn serialize_lossy<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&path.to_string_lossy())
+}
+
+impl ScanError {
+    fn new(path: PathBuf, err: &Error) -> Self {
+        let kind = match err.kind() {
+            io::ErrorKind::PermissionDenied => ScanErrorKind::PermissionDenied,
+            io::ErrorKind::NotFound => ScanErrorKind::Vanished,
+            _ => ScanErrorKind::Io,
+        };
+        ScanError {
+            path,
+            kind,
+            message: err.to_string(),
+        }
+    }
+}
+
+/// Which symlinks to directories the scanner walks into. Symlinks to files, and
+/// to directories inside the scanned root, are never followed: their target is
+/// counted where it lives.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SymlinkPolicy {
+    Never,
+    /// None that lead out of the scanned root, but the loops back into a
+    /// directory being scanned are reported.
+    WithinRoot,
+    Always,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScanOptions {
+    /// Size of the thread pool, 0 uses one thread per logical core.
+    pub threads: usize,
+    /// Count a file with several hard links once, under the first path seen.
+    pub dedup_hard_links: bool,
+    pub symlinks: SymlinkPolicy,
+    /// Stop at mount points instead of walking into other file systems.
+    pub one_file_system: bool,
+    /// Paths to leave out, in gitignore syntax relative to the root of the
+    /// tree. The patterns of `IGNORE_FILE` are added to them.
+    pub excludes: Vec<String>,
+    /// Still measure the excluded paths, into a single `Kind::Excluded` node
+    /// so that the totals stay honest.
+    pub count_excluded: bool,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions {
+            threads: 0,
+            dedup_hard_links: true,
+            symlinks: SymlinkPolicy::Never,
+            one_file_system: false,
+            excludes: Vec::new(),
+            count_excluded: false,
+        }
+    }
+}
+
+/// The hard links met by the scans of a tree, by the directory holding them,
+/// so that scanning a node again neither counts a file twice nor loses the
+/// size left out of the rest of the tree.
+#[derive(Default)]
+pub struct HardLinks {
+    counted: HashMap<FileId, NodeId>,
+    deduplicated: Vec<(NodeId, Size)>,
+}
+
+impl HardLinks {
+    /// Size of the links that were not counted again.
+    pub fn deduplicated(&self) -> Size {
+        let mut total = Size::default();
+        for (_, size) in &self.deduplicated {
+            total += *size;
+        }
+        total
+    }
+
+    /// Follows the ids of the tree changed by `Tree::compact`.
+    pub fn renumber(&mut self, ids: &[Option<NodeId>]) {
+        self.counted.retain(|_, dir| match ids[*dir as usize] {
+            Some(id) => {
+                *dir = id;
+                true
+            }
+            None => false,
+        });
+        self.deduplicated
+            .retain_mut(|(dir, _)| match ids[*dir as usize] {
+                Some(id) => {
+                    *dir = id;
+                    true
+                }
+                None => false,
+            });
+    }
+
+    // The links below detached nodes are no longer in the tree.
+    fn retain_attached(&mut self, tree: &Tree) {
+        self.counted.retain(|_, dir| tree.is_attached(*dir));
+        self.deduplicated.retain(|(dir, _)| tree.is_attached(*dir));
+    }
+}
+
+// The GUI only reads the errors, the tree and `HardLinks` hold the rest.
+#[allow(dead_code)]
+pub struct ScanSummary {
+    pub total: Size,
+    pub completed: bool,
+    pub errors: Vec<ScanError>,
+    /// Size of the hard links that were not counted again.
+    pub deduplicated: Size,
+    /// Size of the excluded paths, measured with `count_excluded` only.
+    pub excluded: Size,
+}
+
+#[cfg(unix)]
+fn allocated_size(metadata: &fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    // st_blocks is always counted in 512-byte units, whatever the block size.
+    metadata.blocks() * 512
+}
+
+#[cfg(not(unix))]
+fn allocated_size(metadata: &fs::Metadata) -> u64 {
+    metadata.len()
+}
+
+// Seconds since the epoch, 0 when unknown.
+fn timestamp(time: io::Result<SystemTime>) -> u32 {
+    time.ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since| since.as_secs().min(u32::MAX as u64) as u32)
+}
+
+fn times(metadata: &fs::Metadata) -> Times {
+    (
+        timestamp(metadata.modified()),
+        timestamp(metadata.accessed()),
+    )
+}
+
+#[cfg(unix)]
+fn file_id(metadata: &fs::Metadata) -> Option<FileId> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+// Without a stable file id symlinked directories cannot be checked for loops,
+// so they are not followed.
+#[cfg(not(unix))]
+fn file_id(_metadata: &fs::Metadata) -> Option<FileId> {
+    None
+}
+
+fn excludes(root: &Path, patterns: &[String]) -> Result<Gitignore, Error> {
+    let invalid = |err| Error::new(io::ErrorKind::InvalidInput, err);
+    let mut builder = GitignoreBuilder::new(root);
+    let file = root.join(IGNORE_FILE);
+    if file.is_file() {
+        if let Some(err) = builder.add(&file) {
+            return Err(invalid(err));
+        }
+    }
+    for pattern in patterns {
+        builder.add_line(None, pattern).map_err(invalid)?;
+    }
+    builder.build().map_err(invalid)
+}
+
+// Files below an excluded path, walked serially and without following
+// anything. Like in the tree, directories themselves weigh nothing.
+fn measure(path: &Path, metadata: &fs::Metadata) -> Size {
+    if !metadata.is_dir() {
+        return Size {
+            apparent: metadata.len(),
+            allocated: allocated_size(metadata),
+        };
+    }
+    let mut size = Size::default();
+    if let Ok(dir) = fs::read_dir(path) {
+        for entry in dir.flatten() {
+            if let Ok(metadata) = entry.metadata() {
+                size += measure(&entry.path(), &metadata);
+            }
+        }
+    }
+    size
+}
+
+// The ids of the directories being walked, from the one being scanned
+// up to the root, to notice a symlink leading back into one of them.
+struct Ancestor<'a> {
+    id: Option<FileId>,
+    parent: Option<&'a Ancestor<'a>>,
+}
+
+impl Ancestor<'_> {
+    fn contains(&self, id: FileId) -> bool {
+        let mut ancestor = Some(self);
+        while let Some(v) = ancestor {
+            if v.id == Some(id) {
+                return true;
+            }
+            ancestor = v.parent;
+        }
+        false
+    }
+}
+
+struct Shared<'a> {
+    options: &'a ScanOptions,
+    root: PathBuf, // of the tree, canonical
+    root_id: Option<FileId>,
+    excludes: Gitignore,
+    canceled: AtomicBool,
+    skipped: Mutex<Vec<ScanError>>,
+    hard_links: Mutex<HardLinks>,
+    visited: Mutex<HashSet<FileId>>, // directories, when following symlinks
+    excluded: Mutex<Size>,
+}
+
+impl Shared<'_> {
+    fn is_canceled(&self) -> bool {
+        self.canceled.load(Ordering::Relaxed)
+    }
+
+    fn cancel(&self) {
+        self.canceled.store(true, Ordering::Relaxed);
+    }
+
+    fn skip(&self, path: PathBuf, err: &Error) {
+        self.skipped.lock().unwrap().push(ScanError::new(path, err));
+    }
+
+    fn is_excluded(&self, path: &Path, metadata: &fs::Metadata) -> bool {
+        if self.excludes.is_empty() || !self.excludes.matched(path, metadata.is_dir()).is_ignore() {
+            return false;
+        }
+        if self.options.count_excluded {
+            // Measured first, the other threads wait on the lock otherwise.
+            let size = measure(path, metadata);
+            *self.excluded.lock().unwrap() += size;
+        }
+        true
+    }
+
+    // Whether the file was already counted under another of its hard links,
+    // it is counted in `dir` otherwise.
+    #[cfg(unix)]
+    fn is_counted(&self, metadata: &fs::Metadata, dir: NodeId, size: Size) -> bool {
+        use std::os::unix::fs::MetadataExt;
+        if metadata.nlink() <= 1 {
+            return false;
+        }
+        let mut hard_links = self.hard_links.lock().unwrap();
+        match hard_links.counted.entry((metadata.dev(), metadata.ino())) {
+            hash_map::Entry::Occupied(_) => {
+                hard_links.deduplicated.push((dir, size));
+                true
+            }
+            hash_map::Entry::Vacant(entry) => {
+                entry.insert(dir);
+                false
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn is_counted(&self, _metadata: &fs::Metadata, _dir: NodeId, _size: Size) -> bool {
+        false
+    }
+
+    // Of a file in `dir`.
+    fn file_size(&self, metadata: &fs::Metadata, dir: NodeId) -> Size {
+        let size = Size {
+            apparent: metadata.len(),
+            allocated: allocated_size(metadata),
+        };
+        // The extra links stay in the tree, only their size goes.
+        if self.options.dedup_hard_links && self.is_counted(metadata, dir, size) {
+            return Size::default();
+        }
+        size
+    }
+
+    // Whether the directory is reached for the first time, by its path or
+    // through a symlink. Without following symlinks nothing leads to a
+    // directory twice.
+    fn visit(&self, id: Option<FileId>) -> bool {
+        match id {
+            Some(id) if self.options.symlinks != SymlinkPolicy::Never => {
+                self.visited.lock().unwrap().insert(id)
+            }
+            _ => true,
+        }
+    }
+
+    // The id of the directory the symlink at `path` leads to, if the policy
+    // allows walking into it.
+    fn follow(&self, path: &Path, ancestors: &Ancestor) -> Option<FileId> {
+        if self.options.symlinks == SymlinkPolicy::Never {
+            return None;
+        }
+        let metadata = fs::metadata(path).ok()?;
+        if !metadata.is_dir() {
+            return None;
+        }
+        let target = fs::canonicalize(path).ok()?;
+        if self.options.symlinks == SymlinkPolicy::WithinRoot && !target.starts_with(&self.root) {
+            return None;
+        }
+        let id = file_id(&metadata)?;
+        if self.options.one_file_system && Some(id.0) != self.root_id.map(|id| id.0) {
+            return None;
+        }
+        if ancestors.contains(id) {
+            self.skipped.lock().unwrap().push(ScanError {
+                path: path.to_path_buf(),
+                kind: ScanErrorKind::SymlinkLoop,
+                message: String::from("leads back into a directory being scanned"),
+            });
+            return None;
+        }
+        // Counted where it lives, which the walk may not have reached yet.
+        if target.starts_with(&self.root) {
+            return None;
+        }
+        // Already counted through another symlink.
+        if !self.visit(Some(id)) {
+            return None;
+        }
+        Some(id)
+    }
+}
+
+/// Scans `path` into `tree` below `node` on a work-stealing thread pool.
+/// Subdirectories are fanned out across the pool while `cb` is invoked on the
+/// calling thread with every directory once it has been scanned; returning
+/// `false` from `cb` cancels the scan. Paths that cannot be read are skipped
+/// and listed in the summary. `hard_links` holds those of the earlier scans of
+/// the tree, empty for a new one.
+pub fn scan(
+    tree: &Mutex<Tree>,
+    node: NodeId,
+    path: &Path,
+    options: &ScanOptions,
+    hard_links: &mut HardLinks,
+    cb: &mut impl FnMut(&Path) -> bool,
+) -> Result<ScanSummary, Error> {
+    fn scan_impl(
+        shared: &Shared,
+        tree: &Mutex<Tree>,
+        node: NodeId,
+        path: &Path,
+        ancestors: &Ancestor,
+        tx: &SyncSender<PathBuf>,
+    ) {
+        let mut files: Vec<(OsString, Kind, Size, Times)> = Vec::new();
+        let mut dirs: Vec<(OsString, Kind, Option<FileId>, Times)> = Vec::new();
+        let mut links: Vec<(OsString, fs::Metadata, Times)> = Vec::new();
+
+        let dir = match fs::read_dir(path) {
+            Ok(dir) => dir,
+            Err(err) => {
+                shared.skip(path.to_path_buf(), &err);
+                return;
+            }
+        };
+        for entry in dir {
+            if shared.is_canceled() {
+                return;
+            }
+
+            // A failing iterator may keep failing, so give up on the rest of
+            // the directory rather than retrying.
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    shared.skip(path.to_path_buf(), &err);
+                    break;
+                }
+            };
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(err) => {
+                    shared.skip(entry.path(), &err);
+                    continue;
+                }
+            };
+            if shared.is_excluded(&entry.path(), &metadata) {
+                continue;
+            }
+            let modified = times(&metadata);
+            if metadata.is_dir() {
+                let id = file_id(&metadata);
+                // Another file system is mounted here when the device changes.
+                let kind = if id.map(|id| id.0) != ancestors.id.map(|id| id.0) {
+                    Kind::MountPoint
+                } else {
+                    Kind::Dir
+                };
+                if (kind == Kind::MountPoint && shared.options.one_file_system) || !shared.visit(id)
+                {
+                    files.push((entry.file_name(), kind, Size::default(), modified));
+                } else {
+                    dirs.push((entry.file_name(), kind, id, modified));
+                }
+                continue;
+            }
+            if metadata.file_type().is_symlink() {
+                links.push((entry.file_name(), metadata, modified));
+                continue;
+            }
+            files.push((
+                entry.file_name(),
+                Kind::File,
+                shared.file_size(&metadata, node),
+                modified,
+            ));
+        }
+        // Followed once the directories next to them are visited, so that
+        // these are counted where they live.
+        for (name, metadata, modified) in links {
+            match shared.follow(&path.join(&name), ancestors) {
+                Some(id) => dirs.push((name, Kind::Symlink, Some(id), modified)),
+                None => {
+                    let size = shared.file_size(&metadata, node);
+                    files.push((name, Kind::Symlink, size, modified));
+                }
+            }
+        }
+
+        // One lock per directory keeps contention low with many workers.
+        let dirs: Vec<(NodeId, PathBuf, Option<FileId>)> = {
+            let mut tree = tree.lock().unwrap();
+            let mut total = Size::default();
+            for (name, kind, size, (mtime, atime)) in files {
+                let id = tree.add(node, &name, kind, size, mtime);
+                tree.set_atime(id, atime);
+                total += size;
+            }
+            tree.grow(node, total);
+            dirs.into_iter()
+                .map(|(name, kind, id, (mtime, atime))| {
+                    let node = tree.add(node, &name, kind, Size::default(), mtime);
+                    tree.set_atime(node, atime);
+                    (node, path.join(name), id)
+                })
+                .collect()
+        };
+
+        dirs.into_par_iter().for_each(|(node, dir, id)| {
+            let ancestors = Ancestor {
+                id,
+                parent: Some(ancestors),
+            };
+            scan_impl(shared, tree, node, dir.as_path(), &ancestors, tx);
+            if !shared.is_canceled() {
+                let _ = tx.send(dir);
+            }
+        });
+    }
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.threads)
+        .build()
+        .map_err(Error::other)?;
+    // Patterns and symlinks are anchored at the root of the tree, also when
+    // rescanning a node below it.
+    let tree_root = {
+        let mut tree = tree.lock().unwrap();
+        if let Ok(metadata) = fs::metadata(path) {
+            let (mtime, atime) = times(&metadata);
+            tree.set_mtime(node, mtime);
+            tree.set_atime(node, atime);
+        }
+        hard_links.retain_attached(&tree);
+        tree.path(Tree::ROOT)
+    };
+    let known_links = hard_links.deduplicated.len();
+    let root_id = fs::metadata(path).ok().as_ref().and_then(file_id);
+    let shared = Shared {
+        options,
+        root: fs::canonicalize(&tree_root).unwrap_or_else(|_| tree_root.clone()),
+        root_id,
+        excludes: excludes(&tree_root, &options.excludes)?,
+        canceled: AtomicBool::new(false),
+        skipped: Mutex::new(Vec::new()),
+        hard_links: Mutex::new(std::mem::take(hard_links)),
+        visited: Mutex::new(root_id.into_iter().collect()),
+        excluded: Mutex::new(Size::default()),
+    };
+    let (tx, rx) = sync_channel(EVENT_QUEUE_SIZE);
+
+    pool.in_place_scope(|scope| {
+        let shared = &shared;
+        scope.spawn(move |_| {
+            let root = Ancestor {
+                id: shared.root_id,
+                parent: None,
+            };
+            scan_impl(shared, tree, node, path, &root, &tx)
+        });
+
+        // Keep draining after a cancel so that no worker stays blocked on a full queue.
+        for dir in rx.iter() {
+            if !shared.is_canceled() && !cb(&dir) {
+                shared.cancel();
+            }
+        }
+    });
+
+    let errors = std::mem::take(&mut *shared.skipped.lock().unwrap());
+    let excluded = *shared.excluded.lock().unwrap();
+    *hard_links = shared.hard_links.into_inner().unwrap();
+    let mut deduplicated = Size::default();
+    for (_, size) in &hard_links.deduplicated[known_links..] {
+        deduplicated += *size;
+    }
+    if excluded != Size::default() {
+        let mut tree = tree.lock().unwrap();
+        let id = tree.add(
+            node,
+            OsStr::new("(excluded)"),
+            Kind::Excluded,
+            Size::default(),
+            0,
+        );
+        tree.grow(id, excluded);
+    }
+    Ok(ScanSummary {
+        total: tree.lock().unwrap().size(node),
+        completed: !shared.canceled.into_inner(),
+        errors,
+        deduplicated,
+        excluded,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A directory of its own for a test, removed with what it holds when the
+    // test is done, even when it fails.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("rustitude-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+    }
+
+    impl std::ops::Deref for TempDir {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    // Scans all of `root` into a new tree.
+    fn scan_all(root: &Path, options: &ScanOptions) -> (Tree, ScanSummary) {
+        let tree = Mutex::new(Tree::new(root));
+        let summary = scan(
+            &tree,
+            Tree::ROOT,
+            root,
+            options,
+            &mut HardLinks::default(),
+            &mut |_| true,
+        )
+        .unwrap();
+        (tree.into_inner().unwrap(), summary)
+    }
+
+    fn child(tree: &Tree, id: NodeId, name: &str) -> NodeId {
+        tree.children(id)
+            .find(|child| tree.name(*child) == name)
+            .unwrap()
+    }
+
+    // macOS refuses to create names that are not valid UTF-8.
+    #[cfg(all(unix, not(target_os = "macos")))]
+    #[test]
+    fn scans_non_utf8_names() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let root = TempDir::new("non-utf8");
+        let name = OsStr::from_bytes(b"caf\xe9");
+        fs::create_dir_all(root.join(name)).unwrap();
+        fs::write(root.join(name).join(name), b"12345").unwrap();
+
+        let tree = Mutex::new(Tree::new(&root));
+        let mut scanned = Vec::new();
+        let options = ScanOptions {
+            threads: 2,
+            ..ScanOptions::default()
+        };
+        let summary = scan(
+            &tree,
+            Tree::ROOT,
+            &root,
+            &options,
+            &mut HardLinks::default(),
+            &mut |path| {
+                scanned.push(path.to_path_buf());
+                true
+            },
+        )
+        .unwrap();
+
+        assert!(summary.completed);
+        assert!(summary.errors.is_empty());
+        assert_eq!(summary.total.apparent, 5);
+        assert_eq!(scanned, vec![root.join(name)]);
+
+        let tree = tree.into_inner().unwrap();
+        let dir = tree.children(Tree::ROOT).next().unwrap();
+        assert_eq!(tree.kind(dir), Kind::Dir);
+        assert_eq!(tree.name(dir), name);
+        assert_eq!(tree.size(dir), summary.total);
+        let file = tree.children(dir).next().unwrap();
+        assert_eq!(tree.kind(file), Kind::File);
+        assert_eq!(tree.path(file), root.join(name).join(name));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stops_at_symlink_loops() {
+        use std::os::unix::fs::symlink;
+
+        let base = TempDir::new("symlinks");
+        let root = base.join("root");
+        fs::create_dir_all(root.join("a")).unwrap();
+        fs::create_dir_all(base.join("outside")).unwrap();
+        fs::write(root.join("a").join("file"), b"12345").unwrap();
+        fs::write(base.join("outside").join("file"), b"123").unwrap();
+        symlink(&root, root.join("a").join("loop")).unwrap();
+        symlink(root.join("a"), root.join("inside")).unwrap();
+        symlink(base.join("outside"), root.join("outside")).unwrap();
+
+        let scan_with = |symlinks| {
+            let options = ScanOptions {
+                symlinks,
+                ..ScanOptions::default()
+            };
+            scan_all(&root, &options)
+        };
+
+        let (tree, summary) = scan_with(SymlinkPolicy::Never);
+        let links_and_file = summary.total.apparent;
+        assert!(summary.errors.is_empty());
+        assert_eq!(tree.kind(child(&tree, Tree::ROOT, "inside")), Kind::Symlink);
+        assert_eq!(tree.children(child(&tree, Tree::ROOT, "inside")).count(), 0);
+
+        let (tree, summary) = scan_with(SymlinkPolicy::WithinRoot);
+        // "a" is counted where it lives, not again through "inside".
+        assert_eq!(summary.total.apparent, links_and_file);
+        assert_eq!(tree.children(child(&tree, Tree::ROOT, "inside")).count(), 0);
+        assert_eq!(
+            tree.children(child(&tree, Tree::ROOT, "outside")).count(),
+            0
+        );
+        assert_eq!(summary.errors.len(), 1);
+        assert_eq!(summary.errors[0].kind, ScanErrorKind::SymlinkLoop);
+
+        let (tree, summary) = scan_with(SymlinkPolicy::Always);
+        let outside = child(&tree, Tree::ROOT, "outside");
+        assert_eq!(tree.kind(outside), Kind::Symlink);
+        assert_eq!(tree.size(outside).apparent, 3);
+        // The followed link weighs what it leads to.
+        let link = fs::symlink_metadata(root.join("outside")).unwrap().len();
+        assert_eq!(summary.total.apparent, links_and_file - link + 3);
+        assert_eq!(summary.errors.len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn counts_symlinked_directories_where_they_live() {
+        use std::os::unix::fs::symlink;
+
+        let root = TempDir::new("nested");
+        fs::create_dir_all(root.join("a").join("b")).unwrap();
+        fs::write(root.join("a").join("b").join("file"), b"12345").unwrap();
+        symlink(root.join("a").join("b"), root.join("link")).unwrap();
+
+        let options = ScanOptions {
+            symlinks: SymlinkPolicy::Always,
+            ..ScanOptions::default()
+        };
+        let (tree, summary) = scan_all(&root, &options);
+        let link_size = fs::symlink_metadata(root.join("link")).unwrap().len();
+
+        assert!(summary.errors.is_empty());
+        assert_eq!(summary.total.apparent, 5 + link_size);
+        let link = child(&tree, Tree::ROOT, "link");
+        assert_eq!(tree.kind(link), Kind::Symlink);
+        assert_eq!(tree.children(link).count(), 0);
+        let b = child(&tree, child(&tree, Tree::ROOT, "a"), "b");
+        assert_eq!(tree.size(b).apparent, 5);
+        assert_eq!(tree.kind(child(&tree, b, "file")), Kind::File);
+    }
+
+    #[test]
+    fn skips_excluded_paths() {
+        let root = TempDir::new("excludes");
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::create_dir_all(root.join("src").join("node_modules").join("x")).unwrap();
+        fs::write(root.join(".git").join("HEAD"), b"12345").unwrap();
+        fs::write(
+            root.join("src").join("node_modules").join("x").join("a"),
+            b"1234567",
+        )
+        .unwrap();
+        fs::write(root.join("src").join("main.rs"), b"123").unwrap();
+        fs::write(root.join(IGNORE_FILE), b"node_modules/\n").unwrap();
+
+        let scan_with = |count_excluded| {
+            let options = ScanOptions {
+                excludes: vec![String::from("/.git")],
+                count_excluded,
+                ..ScanOptions::default()
+            };
+            scan_all(&root, &options)
+        };
+
+        let (tree, summary) = scan_with(false);
+        assert_eq!(summary.total.apparent, 3 + 14);
+        assert_eq!(summary.excluded, Size::default());
+        assert_eq!(tree.len(), 4);
+
+        let (tree, summary) = scan_with(true);
+        assert_eq!(summary.total.apparent, 3 + 14 + 5 + 7);
+        assert_eq!(summary.excluded.apparent, 5 + 7);
+        let excluded = tree
+            .children(Tree::ROOT)
+            .find(|child| tree.kind(*child) == Kind::Excluded)
+            .unwrap();
+        assert_eq!(tree.size(excluded), summary.excluded);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn counts_hard_links_once() {
+        let root = TempDir::new("hard-links");
+        fs::write(root.join("first"), b"1234567890").unwrap();
+        fs::hard_link(root.join("first"), root.join("second")).unwrap();
+
+        let scan_with = |dedup_hard_links| {
+            let options = ScanOptions {
+                dedup_hard_links,
+                ..ScanOptions::default()
+            };
+            scan_all(&root, &options).1
+        };
+        let once = scan_with(true);
+        let twice = scan_with(false);
+
+        assert_eq!(once.total.apparent, 10);
+        assert_eq!(once.deduplicated.apparent, 10);
+        assert_eq!(twice.total.apparent, 20);
+        assert_eq!(twice.deduplicated, Size::default());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn skips_unreadable_directories() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = TempDir::new("unreadable");
+        let locked = root.join("locked");
+        fs::create_dir_all(&locked).unwrap();
+        fs::write(locked.join("hidden"), b"12345").unwrap();
+        fs::write(root.join("seen"), b"123").unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+        // Root reads everything, there is nothing to check then.
+        let readable = fs::read_dir(&locked).is_ok();
+
+        let tree = Mutex::new(Tree::new(&root));
+        let summary = scan(
+            &tree,
+            Tree::ROOT,
+            &root,
+            &ScanOptions::default(),
+            &mut HardLinks::default(),
+            &mut |_| true,
+        );
+        // Or it cannot be removed.
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+        if readable {
+            return;
+        }
+
+        let summary = summary.unwrap();
+        assert!(summary.completed);
+        assert_eq!(summary.total.apparent, 3);
+        assert_eq!(summary.errors.len(), 1);
+        assert_eq!(summary.errors[0].path, locked);
+        assert_eq!(summary.errors[0].kind, ScanErrorKind::PermissionDenied);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rescans_a_subtree() {
+        let root = TempDir::new("rescan");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("sub").join("big"), b"1234567890").unwrap();
+        fs::write(root.join("kept"), b"123").unwrap();
+        fs::hard_link(root.join("kept"), root.join("sub").join("twin")).unwrap();
+
+        let tree = Mutex::new(Tree::new(&root));
+        let options = ScanOptions::default();
+        let mut hard_links = HardLinks::default();
+        scan(
+            &tree,
+            Tree::ROOT,
+            &root,
+            &options,
+            &mut hard_links,
+            &mut |_| true,
+        )
+        .unwrap();
+        fs::write(root.join("sub").join("big"), b"12").unwrap();
+
+        // The old node is replaced by an empty one that the scan fills.
+        let node = {
+            let mut tree = tree.lock().unwrap();
+            let old = child(&tree, Tree::ROOT, "sub");
+            let parent = tree.detach(old);
+            let ids = tree.compact(&[]);
+            hard_links.renumber(&ids);
+            let parent = ids[parent as usize].unwrap();
+            tree.add(parent, OsStr::new("sub"), Kind::Dir, Size::default(), 0)
+        };
+        let path = root.join("sub");
+        let summary = scan(&tree, node, &path, &options, &mut hard_links, &mut |_| true).unwrap();
+
+        // Whichever link was counted first, the file is counted once.
+        let tree = tree.into_inner().unwrap();
+        assert!(summary.completed);
+        assert_eq!(tree.size(Tree::ROOT).apparent, 2 + 3);
+        assert_eq!(hard_links.deduplicated().apparent, 3);
+        assert_eq!(tree.children(Tree::ROOT).count(), 2);
+        assert_eq!(tree.len(), 5);
+    }
 }
@@ -1,60 +1,320 @@
 #![windows_subsystem = "windows"]
 
+mod category;
+mod cli;
+mod diff;
 mod dir;
+mod ncdu;
+mod snapshot;
+mod trash;
+mod tree;
 
+use category::{Categories, CategoryId};
 use druid::{
-    kurbo::{Circle, CircleSegment, Shape},
-    piet::{Text, TextLayout, TextLayoutBuilder},
-    widget::{Flex, Label},
+    kurbo::{Circle, CircleSegment, Rect, Shape},
+    piet::{PietText, PietTextLayout, Text, TextLayout, TextLayoutBuilder},
+    widget::{Flex, Label, List, Scroll},
 };
 use druid::{
     AppDelegate, AppLauncher, Color, Command, Data, DelegateCtx, Env, Event, ExtEventSink, Handled,
-    LifeCycle, PaintCtx, Point, RenderContext, Selector, Target, Widget, WidgetExt, WindowDesc,
+    Lens, LifeCycle, Menu, MenuItem, PaintCtx, Point, RenderContext, Selector, Target, Widget,
+    WidgetExt, WindowDesc,
 };
 use std::{
-    collections::{hash_map::HashMap, VecDeque},
-    path::PathBuf,
+    cmp::Reverse,
+    collections::VecDeque,
+    ffi::OsStr,
+    path::{Path, PathBuf},
     sync::{
         mpsc::{channel, Sender},
-        Arc,
+        Arc, Mutex,
     },
     thread::JoinHandle,
     time::Instant,
 };
+use tree::{Counts, Kind, NodeId, Size, SizeMode, Tree};
 
-const SET_SCANNING: Selector<String> = Selector::new("set_scanning");
+const SET_SCANNING: Selector<PathBuf> = Selector::new("set_scanning");
 const SET_ENTRY: Selector<Arc<Entry>> = Selector::new("set_entry");
 const SET_ERROR: Selector<String> = Selector::new("set_error");
+const SET_SCAN_ERRORS: Selector<(PathBuf, Arc<Vec<Arc<dir::ScanError>>>)> =
+    Selector::new("set_scan_errors");
+const SET_DEDUPLICATED: Selector<Size> = Selector::new("set_deduplicated");
 const NOTIFY_SCAN_FINISH: Selector<()> = Selector::new("notify_scan_finish");
 const REQUEST_SCAN: Selector<PathBuf> = Selector::new("request_scan");
+const REQUEST_SCAN_NODE: Selector<NodeId> = Selector::new("request_scan_node");
+const REQUEST_RESCAN: Selector<Arc<Entry>> = Selector::new("request_rescan");
 const REQUEST_REFRESH: Selector<()> = Selector::new("request_refresh");
 const REQUEST_OPEN_DIALOG: Selector<()> = Selector::new("request_open_dialog");
+const REQUEST_SHOW_ERRORS: Selector<()> = Selector::new("request_show_errors");
+const SET_SNAPSHOT: Selector<(PathBuf, Arc<snapshot::Info>)> = Selector::new("set_snapshot");
+const NOTIFY_SNAPSHOT_SAVED: Selector<PathBuf> = Selector::new("notify_snapshot_saved");
+const REQUEST_LOAD_SNAPSHOT: Selector<PathBuf> = Selector::new("request_load_snapshot");
+const REQUEST_SAVE_SNAPSHOT: Selector<()> = Selector::new("request_save_snapshot");
+const REQUEST_OPEN_SNAPSHOT_DIALOG: Selector<()> = Selector::new("request_open_snapshot_dialog");
+const SET_COMPARED: Selector<(u64, u64)> = Selector::new("set_compared");
+const REQUEST_COMPARE: Selector<PathBuf> = Selector::new("request_compare");
+const REQUEST_COMPARE_DIALOG: Selector<()> = Selector::new("request_compare_dialog");
+const REQUEST_STOP_COMPARING: Selector<()> = Selector::new("request_stop_comparing");
+const REQUEST_SHOW_GROWERS: Selector<()> = Selector::new("request_show_growers");
+const REQUEST_SHOW_OTHERS: Selector<Arc<Entry>> = Selector::new("request_show_others");
+const REQUEST_SHOW_SETTINGS: Selector<()> = Selector::new("request_show_settings");
+const REQUEST_ZOOM: Selector<Arc<Entry>> = Selector::new("request_zoom");
+const REQUEST_ZOOM_OUT: Selector<()> = Selector::new("request_zoom_out");
+const REQUEST_OPEN: Selector<PathBuf> = Selector::new("request_open");
+const REQUEST_REVEAL: Selector<PathBuf> = Selector::new("request_reveal");
+const REQUEST_COPY: Selector<String> = Selector::new("request_copy");
+const REQUEST_EXCLUDE: Selector<Arc<Entry>> = Selector::new("request_exclude");
+const REQUEST_TRASH: Selector<Arc<Entry>> = Selector::new("request_trash");
+const REQUEST_DELETE: Selector<Arc<Entry>> = Selector::new("request_delete");
+const NOTIFY_DELETED: Selector<Arc<Entry>> = Selector::new("notify_deleted");
+const REQUEST_UNDO: Selector<()> = Selector::new("request_undo");
+
+const MOUNT_SWEEP_SIZE: f64 = 0.05;
+// Below this even the grouped entries are left out, whatever the settings.
+const MIN_VISIBLE_SWEEP: f64 = 0.002;
+const CHART_MARGIN: f64 = 10.0;
+const LABEL_SIZE: f64 = 10.0;
+const TREEMAP_HEADER: f64 = 24.0;
+const TILE_PADDING: f64 = 3.0;
+const MIN_TILE_AREA: f64 = 4.0;
+
+/// How the chart lays out the entries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum View {
+    Sunburst,
+    Treemap,
+}
+
+/// What the fill of the chart tells.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Coloring {
+    Kind,
+    Category, // of the files, see `Categories`
+    Modified, // how long ago
+    Accessed,
+}
 
-const MAX_COUNT: usize = 20;
-const MAX_DEPTH: u8 = 10;
+const DAY: u64 = 24 * 60 * 60;
 
-const MIN_SWEEP_SIZE: f64 = 0.01;
+// Colors of the ages, from the newest to the oldest, shades in between.
+const AGE_STOPS: [(u64, (u8, u8, u8)); 6] = [
+    (DAY, (0x2c, 0xa0, 0x2c)),
+    (7 * DAY, (0x9a, 0xcd, 0x32)),
+    (30 * DAY, (0xf0, 0xd0, 0x40)),
+    (182 * DAY, (0xf0, 0x90, 0x30)),
+    (365 * DAY, (0xe0, 0x50, 0x40)),
+    (3 * 365 * DAY, (0x80, 0x30, 0x60)),
+];
+const UNKNOWN_COLOR: (u8, u8, u8) = (0xc8, 0xc8, 0xc8);
+const OTHERS_PAGE_SIZE: usize = 100;
 
 #[derive(Clone, Data)]
 struct Entry {
+    node: NodeId,
     #[data(same_fn = "PartialEq::eq")]
     path: PathBuf,
-    size: u64,
+    size: u64, // by the selected SizeMode
+    #[data(same_fn = "PartialEq::eq")]
+    sizes: Size,
+    #[data(same_fn = "PartialEq::eq")]
+    kind: Kind,
+    #[data(same_fn = "PartialEq::eq")]
+    change: Option<diff::Change>, // when comparing two scans
+    parent_size: u64,
+    mtime: u32,
+    #[data(same_fn = "PartialEq::eq")]
+    counts: Counts,
+    category: Option<CategoryId>,
+    atime: u32,
+    // The newest times of the contents, for directories.
+    modified: u32,
+    accessed: u32,
+    // The children of `node` this entry stands for, when they are too small
+    // to be shown one by one.
+    others: Arc<Vec<NodeId>>,
     children: Arc<Vec<Arc<Entry>>>,
 }
 
 #[derive(Clone, Data)]
+struct Grower {
+    #[data(same_fn = "PartialEq::eq")]
+    path: PathBuf,
+    delta: i64,
+    added: bool,
+}
+
+#[derive(Clone, Data)]
+struct OtherItem {
+    #[data(same_fn = "PartialEq::eq")]
+    path: PathBuf,
+    size: u64,
+}
+
+#[derive(Clone, Data, Lens)]
 struct AppState {
     #[data(same_fn = "PartialEq::eq")]
     current_dir: PathBuf,
     entry: Arc<Entry>,
     total: u64,
     #[data(same_fn = "PartialEq::eq")]
-    scanning_dir: Option<String>,
+    scanning_dir: Option<PathBuf>,
     error: String,
-    header: String, // label
-    expand: String, // label
-    status: String, // label
+    errors: Arc<Vec<Arc<dir::ScanError>>>, // paths skipped by the last scan
+    #[data(same_fn = "PartialEq::eq")]
+    size_mode: SizeMode,
+    #[data(same_fn = "PartialEq::eq")]
+    view: View,
+    label_sizes: bool, // next to the names on the chart
+    #[data(same_fn = "PartialEq::eq")]
+    coloring: Coloring,
+    categories: Arc<Categories>,
+    #[data(same_fn = "PartialEq::eq")]
+    deduplicated: Size, // hard links counted once
+    one_file_system: bool,
+    dedup_hard_links: bool,
+    scanned_at: Option<u64>,      // when showing a snapshot
+    compared: Option<(u64, u64)>, // scan times of the older and the newer scan
+    growers: Arc<Vec<Grower>>,
+    others: Arc<Vec<OtherItem>>, // listed from the chart
+    others_page: usize,
+    others_shown: Arc<Vec<OtherItem>>, // on the page
+    max_depth: usize,
+    max_count: usize, // per directory
+    max_rings: usize,
+    min_segment: f64, // degrees, or share of the treemap
+    #[data(same_fn = "PartialEq::eq")]
+    restorable: Option<PathBuf>, // the last entry moved to the trash
+    header: String,   // label
+    expand: String,   // label
+    status: String,   // label
+}
+
+fn format_size(value: u64) -> String {
+    const KB: u64 = 1024;
+    const MB: u64 = KB * 1024;
+    const GB: u64 = MB * 1024;
+    const TB: u64 = GB * 1024;
+    const PB: u64 = TB * 1024;
+
+    let size = value.to_owned();
+    if size < MB {
+        format!("{:.02} KB", size as f64 / KB as f64)
+    } else if size < GB {
+        format!("{:.02} MB", size as f64 / MB as f64)
+    } else if size < TB {
+        format!("{:.02} GB", size as f64 / GB as f64)
+    } else if size < PB {
+        format!("{:.02} TB", size as f64 / TB as f64)
+    } else {
+        format!("{:.02} PB", size as f64 / PB as f64)
+    }
+}
+
+fn format_delta(value: i64) -> String {
+    let sign = if value < 0 { "-" } else { "+" };
+    format!("{}{}", sign, format_size(value.unsigned_abs()))
+}
+
+fn header(data: &AppState) -> String {
+    let mut header = String::from("Press F5 to refresh");
+    header += match data.size_mode {
+        SizeMode::Apparent => ", S to show allocated sizes",
+        SizeMode::Allocated => ", S to show apparent sizes",
+    };
+    header += match data.view {
+        View::Sunburst => ", T to show a treemap",
+        View::Treemap => ", T to show the sunburst",
+    };
+    header += if data.label_sizes {
+        ", L to hide sizes on the chart"
+    } else {
+        ", L to show sizes on the chart"
+    };
+    header += match data.coloring {
+        Coloring::Kind => ", C to color by file type",
+        Coloring::Category => ", C to color by modification time",
+        Coloring::Modified => ", C to color by access time",
+        Coloring::Accessed => ", C to color by kind",
+    };
+    header += if data.one_file_system {
+        ", X to cross file systems"
+    } else {
+        ", X to stay on one file system"
+    };
+    header += if data.dedup_hard_links {
+        ", H to count hard links every time"
+    } else {
+        ", H to count hard links once"
+    };
+    header += ", P to change the settings, Ctrl+S to save, Ctrl+O to open a snapshot";
+    header += if data.compared.is_some() {
+        ", D to stop comparing, G to list the top growers"
+    } else {
+        ", D to compare with a snapshot"
+    };
+    if let Some(path) = &data.restorable {
+        let name = path.file_name().unwrap_or(path.as_os_str());
+        header += &format!(", Ctrl+Z to put back {}", name.to_string_lossy());
+    }
+    if !data.errors.is_empty() {
+        header += &format!(", E to list {} skipped paths", data.errors.len());
+    }
+    header
+}
+
+// YYYY-MM-DD in UTC.
+fn format_date(seconds: u64) -> String {
+    let (year, month, day) = snapshot::date(seconds);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn format_age(seconds: u64) -> String {
+    const MINUTE: u64 = 60;
+    const HOUR: u64 = MINUTE * 60;
+    const DAY: u64 = HOUR * 24;
+
+    if seconds < HOUR {
+        format!("{} minutes", seconds / MINUTE)
+    } else if seconds < DAY {
+        format!("{} hours", seconds / HOUR)
+    } else {
+        format!("{} days", seconds / DAY)
+    }
+}
+
+// Set as soon as a scan starts, the tree is being changed from then on.
+fn set_scanning(data: &mut AppState, path: &Path) {
+    data.scanning_dir = Some(path.to_path_buf());
+    data.status = format!("Scanning {}", path.display());
+}
+
+fn scan_status(data: &AppState, path: &Path) -> String {
+    let now = snapshot::now();
+    let mut status = if let Some((old, new)) = data.compared {
+        format!(
+            "Changes to {} between {} ago and {} ago",
+            path.display(),
+            format_age(now.saturating_sub(old)),
+            format_age(now.saturating_sub(new))
+        )
+    } else if let Some(scanned_at) = data.scanned_at {
+        format!(
+            "Snapshot of {} taken {} ago",
+            path.display(),
+            format_age(now.saturating_sub(scanned_at))
+        )
+    } else {
+        format!("Scan of {}", path.display())
+    };
+    let deduplicated = data.deduplicated.get(data.size_mode);
+    if deduplicated != 0 {
+        status += &format!(
+            " ({} of hard links counted once)",
+            format_size(deduplicated)
+        );
+    }
+    status
 }
 
 fn open_directory_dialog() -> Option<PathBuf> {
@@ -64,23 +324,175 @@ fn open_directory_dialog() -> Option<PathBuf> {
     }
 }
 
+fn open_snapshot_dialog() -> Option<PathBuf> {
+    let patterns = [
+        format!("*.{}", snapshot::EXTENSION),
+        format!("*.{}", ncdu::EXTENSION),
+    ];
+    tinyfiledialogs::open_file_dialog(
+        "Open snapshot",
+        "",
+        Some((
+            &[patterns[0].as_str(), patterns[1].as_str()],
+            "Rustitude snapshots, ncdu dumps",
+        )),
+    )
+    .map(PathBuf::from)
+}
+
+fn save_snapshot_dialog(dir: &Path) -> Option<PathBuf> {
+    let name = dir.file_name().unwrap_or(dir.as_os_str());
+    let path = PathBuf::from(name).with_extension(snapshot::EXTENSION);
+    let patterns = [
+        format!("*.{}", snapshot::EXTENSION),
+        format!("*.{}", ncdu::EXTENSION),
+    ];
+    tinyfiledialogs::save_file_dialog_with_filter(
+        "Save snapshot",
+        &path.to_string_lossy(),
+        &[patterns[0].as_str(), patterns[1].as_str()],
+        "Rustitude snapshots, ncdu dumps (.json)",
+    )
+    .map(PathBuf::from)
+}
+
+// Shows `path` selected in the file manager. On other systems than Windows
+// and macOS this needs a file manager implementing org.freedesktop.FileManager1,
+// the directory holding `path` is opened otherwise.
+fn reveal(path: &Path) -> Result<(), String> {
+    #[cfg(target_os = "windows")]
+    let shown = std::process::Command::new("explorer")
+        .arg(format!("/select,{}", path.display()))
+        .spawn()
+        .is_ok();
+    #[cfg(target_os = "macos")]
+    let shown = std::process::Command::new("open")
+        .arg("-R")
+        .arg(path)
+        .status()
+        .is_ok_and(|status| status.success());
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    let shown = std::process::Command::new("dbus-send")
+        .args([
+            "--session",
+            "--print-reply",
+            "--dest=org.freedesktop.FileManager1",
+            "/org/freedesktop/FileManager1",
+            "org.freedesktop.FileManager1.ShowItems",
+        ])
+        .arg(format!("array:string:file://{}", trash::encode_path(path)))
+        .arg("string:")
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success());
+
+    if shown {
+        return Ok(());
+    }
+    let dir = path.parent().unwrap_or(path);
+    opener::open(dir).map_err(|err| format!("Error: {}: {}", dir.display(), err))
+}
+
+fn exit_with_error(err: &str) -> ! {
+    // There may be no terminal to print to.
+    eprintln!("rustitude: {}", err);
+    tinyfiledialogs::message_box_ok("Rustitude", err, tinyfiledialogs::MessageBoxIcon::Error);
+    std::process::exit(1);
+}
+
+// The window has no console of its own, started from a terminal the usage,
+// the errors and the output of the subcommands go to that one.
+#[cfg(target_os = "windows")]
+fn attach_console() {
+    use winapi::um::wincon::{AttachConsole, ATTACH_PARENT_PROCESS};
+    // Fails when started from the desktop, there is nothing to print to then.
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn attach_console() {}
+
 fn main() {
-    let selected_dir = open_directory_dialog();
-    if selected_dir.is_none() {
-        return;
+    attach_console();
+    let matches = cli::app().get_matches();
+    if let Some(matches) = matches.subcommand_matches("scan") {
+        std::process::exit(cli::scan(matches));
+    }
+    if let Some(matches) = matches.subcommand_matches("diff") {
+        std::process::exit(cli::diff(matches));
+    }
+    if matches.subcommand_matches("categories").is_some() {
+        std::process::exit(cli::print_categories());
     }
 
-    let window = WindowDesc::new(ui_builder())
+    let categories = cli::categories(&matches).unwrap_or_else(|err| exit_with_error(&err));
+    let selected = match matches.value_of_os("path") {
+        Some(path) => match cli::source(path) {
+            Ok(dir) => dir,
+            Err(err) => exit_with_error(&err),
+        },
+        None => match open_directory_dialog() {
+            Some(dir) => dir,
+            None => return,
+        },
+    };
+    let options = cli::scan_options(&matches);
+
+    let window = WindowDesc::new(ui_builder(options.clone()))
         .window_size((960.0, 540.0))
         .title("Rustitude");
     let launcher = AppLauncher::with_window(window);
 
-    let data = AppState {
-        current_dir: selected_dir.unwrap(),
+    // A snapshot is read once the window is up, nothing gets scanned until then.
+    let (current_dir, snapshot) = if selected.is_file() {
+        (PathBuf::new(), Some(selected))
+    } else {
+        (selected, None)
+    };
+    if let Some(path) = snapshot {
+        launcher
+            .get_external_handle()
+            .submit_command(REQUEST_LOAD_SNAPSHOT, path, Target::Auto)
+            .unwrap();
+    }
+
+    let data = initial_state(current_dir, &matches, categories, &options);
+
+    launcher
+        .delegate(Delegate {})
+        .launch(data)
+        .expect("launch failed");
+}
+
+// The scan of `current_dir` starts with the window, a snapshot is opened
+// instead when it is empty. The view follows the command line.
+fn initial_state(
+    current_dir: PathBuf,
+    matches: &clap::ArgMatches,
+    categories: Categories,
+    options: &dir::ScanOptions,
+) -> AppState {
+    let mut data = AppState {
+        current_dir,
         entry: Arc::new(Entry {
             children: Arc::new(Vec::new()),
+            node: Tree::ROOT,
             path: PathBuf::new(),
             size: 0u64,
+            sizes: Size::default(),
+            kind: Kind::Dir,
+            change: None,
+            parent_size: 0u64,
+            mtime: 0,
+            counts: Counts::default(),
+            category: None,
+            atime: 0,
+            modified: 0,
+            accessed: 0,
+            others: Arc::new(Vec::new()),
         }),
         total: 0u64,
         header: String::new(),
@@ -88,12 +500,32 @@ fn main() {
         status: String::new(),
         scanning_dir: None,
         error: String::new(),
+        errors: Arc::new(Vec::new()),
+        size_mode: SizeMode::Apparent,
+        view: View::Sunburst,
+        label_sizes: false,
+        coloring: Coloring::Kind,
+        categories: Arc::new(categories),
+        deduplicated: Size::default(),
+        one_file_system: options.one_file_system,
+        dedup_hard_links: options.dedup_hard_links,
+        scanned_at: None,
+        compared: None,
+        growers: Arc::new(Vec::new()),
+        others: Arc::new(Vec::new()),
+        others_page: 0,
+        others_shown: Arc::new(Vec::new()),
+        max_depth: cli::depth(matches),
+        max_count: cli::top(matches),
+        max_rings: cli::rings(matches),
+        min_segment: cli::min_segment(matches),
+        restorable: None,
     };
-
-    launcher
-        .delegate(Delegate {})
-        .launch(data)
-        .expect("launch failed");
+    if !data.current_dir.as_os_str().is_empty() {
+        let path = data.current_dir.clone();
+        set_scanning(&mut data, &path);
+    }
+    data
 }
 
 struct Delegate {}
@@ -103,15 +535,86 @@ impl AppDelegate<AppState> for Delegate {
         ctx: &mut DelegateCtx,
         _window_id: druid::WindowId,
         event: Event,
-        _data: &mut AppState,
+        data: &mut AppState,
         _env: &Env,
     ) -> Option<Event> {
         match &event {
             Event::KeyDown(v) => {
-                if v.key == druid::keyboard_types::Key::F5 {
+                if druid::HotKey::new(druid::SysMods::Cmd, "s").matches(v) {
+                    ctx.get_external_handle()
+                        .submit_command(REQUEST_SAVE_SNAPSHOT, (), Target::Auto)
+                        .unwrap();
+                } else if druid::HotKey::new(druid::SysMods::Cmd, "z").matches(v) {
+                    if data.scanning_dir.is_none() && data.compared.is_none() {
+                        ctx.get_external_handle()
+                            .submit_command(REQUEST_UNDO, (), Target::Auto)
+                            .unwrap();
+                    }
+                } else if druid::HotKey::new(druid::SysMods::Cmd, "o").matches(v) {
+                    ctx.get_external_handle()
+                        .submit_command(REQUEST_OPEN_SNAPSHOT_DIALOG, (), Target::Auto)
+                        .unwrap();
+                } else if v.key == druid::keyboard_types::Key::F5 {
                     ctx.get_external_handle()
                         .submit_command(REQUEST_REFRESH, (), Target::Auto)
                         .unwrap();
+                } else if druid::HotKey::new(None, "e").matches(v) {
+                    ctx.get_external_handle()
+                        .submit_command(REQUEST_SHOW_ERRORS, (), Target::Auto)
+                        .unwrap();
+                } else if druid::HotKey::new(None, "s").matches(v) {
+                    data.size_mode = match data.size_mode {
+                        SizeMode::Apparent => SizeMode::Allocated,
+                        SizeMode::Allocated => SizeMode::Apparent,
+                    };
+                    data.header = header(data);
+                    if data.scanning_dir.is_none() {
+                        data.status = scan_status(data, &data.current_dir);
+                    }
+                } else if druid::HotKey::new(None, "t").matches(v) {
+                    data.view = match data.view {
+                        View::Sunburst => View::Treemap,
+                        View::Treemap => View::Sunburst,
+                    };
+                    data.header = header(data);
+                } else if druid::HotKey::new(None, "c").matches(v) {
+                    data.coloring = match data.coloring {
+                        Coloring::Kind => Coloring::Category,
+                        Coloring::Category => Coloring::Modified,
+                        Coloring::Modified => Coloring::Accessed,
+                        Coloring::Accessed => Coloring::Kind,
+                    };
+                    data.header = header(data);
+                } else if druid::HotKey::new(None, "p").matches(v) {
+                    ctx.get_external_handle()
+                        .submit_command(REQUEST_SHOW_SETTINGS, (), Target::Auto)
+                        .unwrap();
+                } else if druid::HotKey::new(None, "l").matches(v) {
+                    data.label_sizes = !data.label_sizes;
+                    data.header = header(data);
+                } else if druid::HotKey::new(None, "x").matches(v) {
+                    data.one_file_system = !data.one_file_system;
+                    ctx.get_external_handle()
+                        .submit_command(REQUEST_REFRESH, (), Target::Auto)
+                        .unwrap();
+                } else if druid::HotKey::new(None, "h").matches(v) {
+                    data.dedup_hard_links = !data.dedup_hard_links;
+                    ctx.get_external_handle()
+                        .submit_command(REQUEST_REFRESH, (), Target::Auto)
+                        .unwrap();
+                } else if druid::HotKey::new(None, "d").matches(v) && data.scanning_dir.is_none() {
+                    let request = if data.compared.is_some() {
+                        REQUEST_STOP_COMPARING
+                    } else {
+                        REQUEST_COMPARE_DIALOG
+                    };
+                    ctx.get_external_handle()
+                        .submit_command(request, (), Target::Auto)
+                        .unwrap();
+                } else if druid::HotKey::new(None, "g").matches(v) && data.compared.is_some() {
+                    ctx.get_external_handle()
+                        .submit_command(REQUEST_SHOW_GROWERS, (), Target::Auto)
+                        .unwrap();
                 }
             }
             _ => {}
@@ -121,7 +624,7 @@ impl AppDelegate<AppState> for Delegate {
 
     fn command(
         &mut self,
-        _ctx: &mut DelegateCtx,
+        ctx: &mut DelegateCtx,
         _target: Target,
         cmd: &Command,
         data: &mut AppState,
@@ -130,18 +633,85 @@ impl AppDelegate<AppState> for Delegate {
         if let Some(value) = cmd.get(REQUEST_SCAN) {
             data.current_dir = value.clone();
             data.header = String::new();
+            data.errors = Arc::new(Vec::new());
+            data.deduplicated = Size::default();
+            data.scanned_at = None;
+            data.compared = None;
+            data.restorable = None;
+        } else if let Some(_) = cmd.get(REQUEST_REFRESH) {
+            data.deduplicated = Size::default();
+            data.scanned_at = None;
+            data.compared = None;
+            data.restorable = None;
+        } else if let Some(value) = cmd.get(REQUEST_LOAD_SNAPSHOT) {
+            data.scanning_dir = Some(value.clone());
+            data.status = format!("Loading {}", value.display());
+        } else if let Some(value) = cmd.get(REQUEST_COMPARE) {
+            data.scanning_dir = Some(value.clone());
+            data.status = format!("Comparing with {}", value.display());
+        } else if let Some(value) = cmd.get(SET_COMPARED) {
+            data.compared = Some(*value);
+        } else if cmd.is(REQUEST_STOP_COMPARING) {
+            data.compared = None;
+            data.growers = Arc::new(Vec::new());
+            data.status = scan_status(data, &data.current_dir);
+            data.header = header(data);
+        } else if let Some((root, info)) = cmd.get(SET_SNAPSHOT) {
+            // Sent before the errors of the snapshot.
+            data.restorable = None;
+            data.errors = Arc::new(Vec::new());
+            data.deduplicated = Size::default();
+            data.compared = None;
+            data.current_dir = root.clone();
+            data.scanned_at = Some(info.scanned_at);
+        } else if let Some(value) = cmd.get(NOTIFY_SNAPSHOT_SAVED) {
+            data.status = format!("Saved {}", value.display());
         } else if let Some(value) = cmd.get(SET_ENTRY) {
             data.entry = Arc::from(value.clone());
         } else if let Some(value) = cmd.get(SET_SCANNING) {
-            data.scanning_dir = Some(value.clone());
-            data.status = format!("Scanning {}", value);
+            set_scanning(data, value);
         } else if let Some(_) = cmd.get(NOTIFY_SCAN_FINISH) {
             data.scanning_dir = None;
-            data.status = format!("Scan of {}", data.current_dir.display());
-            data.header = String::from("Press F5 to refresh");
+            data.status = scan_status(data, &data.current_dir);
+            data.header = header(data);
         } else if let Some(value) = cmd.get(SET_ERROR) {
             data.error = value.clone();
             data.status = format!("Error {}", data.error);
+        } else if let Some((path, errors)) = cmd.get(SET_SCAN_ERRORS) {
+            // A scan of a mount point replaces the errors below it only.
+            let mut merged: Vec<Arc<dir::ScanError>> = data
+                .errors
+                .iter()
+                .filter(|error| !error.path.starts_with(path))
+                .cloned()
+                .collect();
+            merged.extend(errors.iter().cloned());
+            data.errors = Arc::new(merged);
+        } else if let Some(value) = cmd.get(SET_DEDUPLICATED) {
+            data.deduplicated = *value;
+        } else if let Some(path) = cmd.get(REQUEST_OPEN) {
+            if let Err(err) = opener::open(path) {
+                data.error = format!("Error: {}: {}", path.display(), err);
+                data.status = data.error.clone();
+            }
+        } else if let Some(path) = cmd.get(REQUEST_REVEAL) {
+            let sink = ctx.get_external_handle();
+            let path = path.clone();
+            std::thread::spawn(move || {
+                if let Err(err) = reveal(&path) {
+                    sink.submit_command(SET_ERROR, err, Target::Auto).unwrap();
+                }
+            });
+        } else if let Some(text) = cmd.get(REQUEST_COPY) {
+            druid::Application::global().clipboard().put_string(text);
+            data.status = format!("Copied {}", text);
+        } else if let Some(_) = cmd.get(REQUEST_SHOW_ERRORS) {
+            if !data.errors.is_empty() {
+                let window = WindowDesc::new(errors_ui())
+                    .window_size((720.0, 360.0))
+                    .title("Rustitude - Skipped paths");
+                ctx.new_window(window);
+            }
         }
         Handled::No
     }
@@ -165,155 +735,633 @@ impl AppDelegate<AppState> for Delegate {
     }
 }
 
+// Turns the nodes of a tree into entries, `tree` is the tree of `diff` when
+// comparing. The passes over the whole tree are empty when nothing shows
+// them, their entries are then left at the default.
+struct Collector<'a> {
+    tree: &'a Tree,
+    diff: Option<&'a diff::Diff>,
+    counts: Vec<Counts>,
+    categories: Vec<Option<CategoryId>>,
+    modified: Vec<u32>,
+    accessed: Vec<u32>,
+    mode: SizeMode,
+    count: usize, // per directory
+    max_depth: usize,
+}
+
+impl Collector<'_> {
+    fn entry(&self, id: NodeId, parent_size: u64, depth: usize) -> Arc<Entry> {
+        let tree = self.tree;
+        let kind = tree.kind(id);
+        let children = if kind != Kind::File {
+            Arc::new(self.collect(id, depth))
+        } else {
+            Arc::new(Vec::new())
+        };
+
+        Arc::new(Entry {
+            node: id,
+            path: tree.path(id),
+            size: tree.size(id).get(self.mode),
+            sizes: tree.size(id),
+            kind: kind,
+            change: self.diff.map(|diff| diff.change(id)),
+            parent_size,
+            mtime: tree.mtime(id),
+            counts: self.counts.get(id as usize).copied().unwrap_or_default(),
+            category: self.categories.get(id as usize).copied().flatten(),
+            atime: tree.atime(id),
+            modified: self.modified.get(id as usize).copied().unwrap_or(0),
+            accessed: self.accessed.get(id as usize).copied().unwrap_or(0),
+            others: Arc::new(Vec::new()),
+            children: children,
+        })
+    }
+
+    fn collect(&self, id: NodeId, depth: usize) -> Vec<Arc<Entry>> {
+        if depth > self.max_depth {
+            return Vec::new();
+        }
+
+        let tree = self.tree;
+        let size = tree.size(id).get(self.mode);
+        let mut children: Vec<NodeId> = tree.children(id).collect();
+        children.sort_by_key(|child| Reverse(tree.size(*child).get(self.mode)));
+
+        // Mount points are kept past the top entries so that they can be scanned.
+        let (shown, hidden): (Vec<_>, Vec<_>) = children
+            .iter()
+            .enumerate()
+            .partition(|(i, child)| *i < self.count || tree.kind(**child) == Kind::MountPoint);
+        let mut entries: Vec<Arc<Entry>> = shown
+            .into_iter()
+            .map(|(_, &child)| self.entry(child, size, depth + 1))
+            .collect();
+        if !hidden.is_empty() {
+            let nodes: Vec<NodeId> = hidden.into_iter().map(|(_, &child)| child).collect();
+            let mut sizes = Size::default();
+            for child in &nodes {
+                sizes += tree.size(*child);
+            }
+            let path = tree.path(id);
+            entries.push(others_entry(
+                id,
+                path,
+                size,
+                nodes,
+                sizes,
+                sizes.get(self.mode),
+            ));
+        }
+        entries
+    }
+}
+
+// Stands for `nodes`, children of `parent` too small to be shown one by one.
+fn others_entry(
+    parent: NodeId,
+    path: PathBuf,
+    parent_size: u64,
+    nodes: Vec<NodeId>,
+    sizes: Size,
+    size: u64,
+) -> Arc<Entry> {
+    Arc::new(Entry {
+        node: parent,
+        path,
+        size,
+        sizes,
+        kind: Kind::File,
+        change: None,
+        parent_size,
+        mtime: 0,
+        counts: Counts::default(),
+        category: None,
+        atime: 0,
+        modified: 0,
+        accessed: 0,
+        others: Arc::new(nodes),
+        children: Arc::new(Vec::new()),
+    })
+}
+
+fn is_others(entry: &Entry) -> bool {
+    !entry.others.is_empty()
+}
+
+// One entry for the children of `parent` in `small`.
+fn fold_others<'a>(parent: &Entry, small: impl Iterator<Item = &'a Arc<Entry>>) -> Arc<Entry> {
+    let mut nodes = Vec::new();
+    let mut sizes = Size::default();
+    let mut size = 0;
+    for v in small {
+        if is_others(v) {
+            nodes.extend(v.others.iter().copied());
+        } else {
+            nodes.push(v.node);
+        }
+        sizes += v.sizes;
+        size += v.size;
+    }
+    others_entry(
+        parent.node,
+        parent.path.clone(),
+        parent.size,
+        nodes,
+        sizes,
+        size,
+    )
+}
+
+// `tree` is the tree of `diff` when comparing. While scanning, only the
+// coloring in use is worked out, the tooltip with the counts and the newest
+// times is shown once the scan is done.
+fn root_entry(tree: &Tree, diff: Option<&diff::Diff>, data: &AppState) -> Arc<Entry> {
+    let mode = data.size_mode;
+    let done = data.scanning_dir.is_none();
+    let collector = Collector {
+        tree,
+        diff,
+        counts: if done { tree.counts() } else { Vec::new() },
+        categories: if data.coloring == Coloring::Category {
+            data.categories.classify(tree, mode)
+        } else {
+            Vec::new()
+        },
+        modified: if done || data.coloring == Coloring::Modified {
+            tree.newest(|id| tree.mtime(id))
+        } else {
+            Vec::new()
+        },
+        accessed: if data.coloring == Coloring::Accessed {
+            tree.newest(|id| tree.atime(id))
+        } else {
+            Vec::new()
+        },
+        mode,
+        count: data.max_count,
+        max_depth: data.max_depth,
+    };
+    collector.entry(Tree::ROOT, tree.size(Tree::ROOT).get(mode), 0)
+}
+
 struct Updater {
     handle: Option<JoinHandle<()>>,
     sender: Option<Sender<bool>>,
+    tree: Arc<Mutex<Tree>>,
+    diff: Arc<Mutex<Option<diff::Diff>>>, // of the tree and a snapshot
+    options: dir::ScanOptions,
+    // What the tree holds, for saving it.
+    scanned_at: u64,
+    scanned_with: dir::ScanOptions,
+    // What can be put back from the trash, the last at the end, with the
+    // parent, unless a rescan dropped it, and the node it was.
+    trashed: Vec<(Option<NodeId>, NodeId, trash::Trashed)>,
+    // Met by the scans of the tree, for rescanning parts of it.
+    hard_links: Arc<Mutex<dir::HardLinks>>,
+    // Left out of a snapshot, whose links are not known.
+    snapshot_deduplicated: Size,
 }
 
 impl Updater {
-    pub fn new() -> Self {
+    pub fn new(options: dir::ScanOptions) -> Self {
         Updater {
             handle: None,
             sender: None,
+            tree: Arc::new(Mutex::new(Tree::new(&PathBuf::new()))),
+            diff: Arc::new(Mutex::new(None)),
+            scanned_at: 0,
+            scanned_with: options.clone(),
+            options,
+            trashed: Vec::new(),
+            hard_links: Arc::new(Mutex::new(dir::HardLinks::default())),
+            snapshot_deduplicated: Size::default(),
         }
     }
 
     fn stop_worker(&mut self) {
         if let Some(x) = self.handle.take() {
-            let result = self.sender.take().unwrap().send(true);
-            if let Err(x) = result {
-                println!("failed to send({}).", x.to_string());
+            // Loading a snapshot cannot be canceled, there is no sender then.
+            if let Some(sender) = self.sender.take() {
+                if let Err(x) = sender.send(true) {
+                    println!("failed to send({}).", x.to_string());
+                }
             }
             x.join().unwrap();
         }
     }
 
-    fn start_worker(&mut self, sink: ExtEventSink, path: PathBuf) {
+    // Scans into `node` of `tree`, the root of a new tree or a mount point
+    // skipped by an earlier scan.
+    fn start_worker(&mut self, sink: ExtEventSink, tree: Arc<Mutex<Tree>>, node: NodeId) {
         let (tx, rx) = channel();
 
-        fn collect(
-            path: PathBuf,
-            cache: &HashMap<String, Vec<(String, u64)>>,
-            count: usize,
-            depth: u8,
-        ) -> Vec<Arc<Entry>> {
-            // println!("generate_entries depth={} path={}", depth, path.clone().display());
-
-            if depth > MAX_DEPTH {
-                return Vec::new();
-            }
-
-            let c = cache.get(path.to_str().unwrap());
-            if c.is_none() {
-                // println!("cache(key) not found.");
-                return Vec::new();
-            }
-
-            let mut filtered = c.unwrap().clone();
-            filtered.sort_by(|a, b| b.1.cmp(&a.1));
-
-            filtered
-                .iter()
-                .take(count)
-                .filter_map(|v| {
-                    let p = PathBuf::from(v.0.clone());
-                    if p == path {
-                        return None;
-                    }
-
-                    let children = if p.is_dir() {
-                        Arc::new(collect(p.clone(), cache, count, depth + 1))
-                    } else {
-                        Arc::new(Vec::new())
-                    };
-
-                    let entry = Entry {
-                        path: p.clone(),
-                        size: v.1,
-                        children: children,
-                    };
-                    Some(Arc::new(entry))
-                })
-                .collect()
-        }
+        // The worker only fills the tree, snapshots for the chart are taken on
+        // the UI thread so that they follow the view settings.
+        let path = tree.lock().unwrap().path(node);
+        self.tree = tree.clone();
+        let options = self.options.clone();
+        let hard_links = self.hard_links.clone();
+        let snapshot_deduplicated = self.snapshot_deduplicated;
 
         let handle = std::thread::spawn(move || {
-            let start = path.clone();
-            println!("starting worker thread for {}.", start.display());
+            println!("starting worker thread for {}.", path.display());
 
-            let mut total: u64 = 0;
             let mut count: u64 = 0;
             const NOTIFY_INTERVAL: u64 = 300;
 
-            let mut cache: HashMap<String, Vec<(String, u64)>> = HashMap::new();
-            cache.reserve(100000);
-
             let now0 = Instant::now();
-            let result = dir::get_directory_size_recursive(
+            let mut hard_links = hard_links.lock().unwrap();
+            let result = dir::scan(
+                &tree,
+                node,
                 path.as_path(),
-                &mut |parent, path, is_dir, size| {
+                &options,
+                &mut hard_links,
+                &mut |dir| {
                     let data = rx.try_recv();
                     if data.unwrap_or(false) {
-                        return Ok(false);
+                        return false;
                     }
 
-                    if is_dir {
-                        cache
-                            .entry(parent.into())
-                            .or_insert_with(Vec::new)
-                            .push((path.into(), size));
-                        // println!("added cache(dir) parent={} path={} size={}", parent, path.display(), size);
-
-                        count += 1;
-                        if count % NOTIFY_INTERVAL == 0 {
-                            let entry = Entry {
-                                path: start.clone(),
-                                size: total,
-                                children: Arc::new(collect(start.clone(), &cache, MAX_COUNT, 0)),
-                            };
-                            sink.submit_command(SET_ENTRY, Arc::from(entry), Target::Auto)
-                                .unwrap();
-                            sink.submit_command(SET_SCANNING, path.to_string(), Target::Auto)
-                                .unwrap();
-                        }
-                    } else {
-                        total += size;
-                        cache
-                            .entry(parent.into())
-                            .or_insert_with(Vec::new)
-                            .push((path.into(), size));
-                        // println!("added cache(file) parent={} path={} size={}", parent, path.display(), size);
+                    count += 1;
+                    if count % NOTIFY_INTERVAL == 0 {
+                        sink.submit_command(SET_SCANNING, dir.to_path_buf(), Target::Auto)
+                            .unwrap();
                     }
 
-                    Ok(true)
+                    true
                 },
             );
             println!("elapsed0 = {}", now0.elapsed().as_millis());
 
-            let now1 = Instant::now();
-            let entry = Entry {
-                path: start.clone(),
-                size: total,
-                children: Arc::new(collect(start.clone(), &cache, MAX_COUNT, 0)),
+            // Sent after the finish, which would overwrite the status.
+            let error = match result {
+                Ok(summary) => {
+                    let errors = summary.errors.into_iter().map(Arc::new).collect();
+                    sink.submit_command(
+                        SET_SCAN_ERRORS,
+                        (path.clone(), Arc::new(errors)),
+                        Target::Auto,
+                    )
+                    .unwrap();
+                    // Worked out again, a rescan drops what was below the node.
+                    let mut deduplicated = snapshot_deduplicated;
+                    deduplicated += hard_links.deduplicated();
+                    sink.submit_command(SET_DEDUPLICATED, deduplicated, Target::Auto)
+                        .unwrap();
+                    None
+                }
+                Err(err) => Some(format!("Error: {}", err.to_string())),
             };
-            sink.submit_command(SET_ENTRY, Arc::from(entry), Target::Auto)
+
+            sink.submit_command(NOTIFY_SCAN_FINISH, (), Target::Auto)
                 .unwrap();
+            if let Some(error) = error {
+                sink.submit_command(SET_ERROR, error, Target::Auto).unwrap();
+            }
+        });
+
+        self.handle = Some(handle);
+        self.sender = Some(tx);
+    }
+
+    fn start_scan(&mut self, sink: ExtEventSink, data: &AppState) {
+        self.options.one_file_system = data.one_file_system;
+        self.options.dedup_hard_links = data.dedup_hard_links;
+        self.scanned_at = snapshot::now();
+        self.scanned_with = self.options.clone();
+        *self.diff.lock().unwrap() = None;
+        self.trashed.clear();
+        self.hard_links = Arc::new(Mutex::new(dir::HardLinks::default()));
+        self.snapshot_deduplicated = Size::default();
+        let tree = Arc::new(Mutex::new(Tree::new(&data.current_dir)));
+        self.start_worker(sink, tree, Tree::ROOT);
+    }
+
+    // The tree is only replaced once the snapshot is read, a failure leaves
+    // the current one on screen.
+    fn start_loader(&mut self, sink: ExtEventSink, path: PathBuf) {
+        let tree = self.tree.clone();
+        let diff = self.diff.clone();
+
+        let handle = std::thread::spawn(move || {
+            let error = match snapshot::open(&path) {
+                Ok((loaded, info)) => {
+                    let root = loaded.path(Tree::ROOT);
+                    *diff.lock().unwrap() = None;
+                    *tree.lock().unwrap() = loaded;
+                    let errors = info.errors.iter().cloned().map(Arc::new).collect();
+                    let deduplicated = info.deduplicated;
+                    sink.submit_command(SET_SNAPSHOT, (root.clone(), Arc::new(info)), Target::Auto)
+                        .unwrap();
+                    sink.submit_command(
+                        SET_SCAN_ERRORS,
+                        (root.clone(), Arc::new(errors)),
+                        Target::Auto,
+                    )
+                    .unwrap();
+                    sink.submit_command(SET_DEDUPLICATED, deduplicated, Target::Auto)
+                        .unwrap();
+                    None
+                }
+                Err(err) => Some(format!("Error: {}: {}", path.display(), err)),
+            };
             sink.submit_command(NOTIFY_SCAN_FINISH, (), Target::Auto)
                 .unwrap();
-            println!("elapsed1 = {}", now1.elapsed().as_millis());
+            if let Some(error) = error {
+                sink.submit_command(SET_ERROR, error, Target::Auto).unwrap();
+            }
+        });
 
-            if let Err(err) = result {
-                sink.submit_command(
-                    SET_ERROR,
-                    format!("Error: {}", err.to_string()),
-                    Target::Auto,
-                )
+        self.handle = Some(handle);
+        self.sender = None;
+    }
+
+    // Compares the tree with the snapshot at `path`, the older of the two is
+    // the baseline.
+    fn start_comparison(&mut self, sink: ExtEventSink, path: PathBuf) {
+        let tree = self.tree.clone();
+        let diff = self.diff.clone();
+        let scanned_at = self.scanned_at;
+
+        let handle = std::thread::spawn(move || {
+            let error = match snapshot::open(&path) {
+                Ok((loaded, info)) => {
+                    // The tree is unlocked before the diff, as everywhere else.
+                    let tree = tree.lock().unwrap();
+                    let (result, compared) = if info.scanned_at <= scanned_at {
+                        (
+                            diff::Diff::new(&loaded, &tree),
+                            (info.scanned_at, scanned_at),
+                        )
+                    } else {
+                        (
+                            diff::Diff::new(&tree, &loaded),
+                            (scanned_at, info.scanned_at),
+                        )
+                    };
+                    drop(tree);
+                    *diff.lock().unwrap() = Some(result);
+                    sink.submit_command(SET_COMPARED, compared, Target::Auto)
+                        .unwrap();
+                    None
+                }
+                Err(err) => Some(format!("Error: {}: {}", path.display(), err)),
+            };
+            sink.submit_command(NOTIFY_SCAN_FINISH, (), Target::Auto)
                 .unwrap();
+            if let Some(error) = error {
+                sink.submit_command(SET_ERROR, error, Target::Auto).unwrap();
             }
         });
 
         self.handle = Some(handle);
-        self.sender = Some(tx);
+        self.sender = None;
+    }
+
+    // The children `entry` stands for, largest first.
+    fn others(&self, data: &AppState, entry: &Entry) -> Vec<OtherItem> {
+        let list = |tree: &Tree| {
+            let mut items: Vec<OtherItem> = entry
+                .others
+                .iter()
+                .map(|id| OtherItem {
+                    path: tree.path(*id),
+                    size: tree.size(*id).get(data.size_mode),
+                })
+                .collect();
+            items.sort_by_key(|item| Reverse(item.size));
+            items
+        };
+        let diff = self.diff.lock().unwrap();
+        if let Some(diff) = &*diff {
+            return list(&diff.tree);
+        }
+        drop(diff);
+        list(&self.tree.lock().unwrap())
+    }
+
+    fn growers(&self, data: &AppState) -> Vec<Grower> {
+        match &*self.diff.lock().unwrap() {
+            Some(diff) => diff
+                .growers(data.size_mode, data.max_count)
+                .into_iter()
+                .map(|id| {
+                    let change = diff.change(id);
+                    Grower {
+                        path: diff.tree.path(id),
+                        delta: change.delta(data.size_mode),
+                        added: change.is_added(),
+                    }
+                })
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn save(&self, sink: ExtEventSink, data: &AppState) {
+        let tree = self.tree.clone();
+        let dir = data.current_dir.clone();
+        let info = snapshot::Info {
+            scanned_at: self.scanned_at,
+            options: self.scanned_with.clone(),
+            errors: data.errors.iter().map(|error| (**error).clone()).collect(),
+            deduplicated: data.deduplicated,
+        };
+        std::thread::spawn(move || {
+            if let Some(path) = save_snapshot_dialog(&dir) {
+                // Saved for ncdu when a .json name is picked.
+                let result = if path.extension() == Some(OsStr::new(ncdu::EXTENSION)) {
+                    ncdu::save(&path, &tree.lock().unwrap(), &info)
+                } else {
+                    snapshot::save(&path, &tree.lock().unwrap(), &info)
+                };
+                match result {
+                    Ok(()) => sink
+                        .submit_command(NOTIFY_SNAPSHOT_SAVED, path, Target::Auto)
+                        .unwrap(),
+                    Err(err) => sink
+                        .submit_command(
+                            SET_ERROR,
+                            format!("Error: {}: {}", path.display(), err),
+                            Target::Auto,
+                        )
+                        .unwrap(),
+                }
+            }
+        });
+    }
+
+    // Whether `entry` stands for a node of the tree on disk, and not of a
+    // diff, of an older scan or of several nodes.
+    fn is_current(&self, data: &AppState, entry: &Entry) -> bool {
+        if !is_live(data) || is_others(entry) {
+            return false;
+        }
+        let tree = self.tree.lock().unwrap();
+        (entry.node as usize) < tree.len()
+            && entry.node != Tree::ROOT
+            && tree.is_attached(entry.node)
+            && tree.path(entry.node) == entry.path
+    }
+
+    fn trash(&mut self, ctx: &mut druid::EventCtx, data: &mut AppState, entry: &Entry) {
+        match trash::trash(&entry.path) {
+            Ok(trashed) => {
+                let parent = self.tree.lock().unwrap().detach(entry.node);
+                self.trashed.push((Some(parent), entry.node, trashed));
+                data.restorable = Some(entry.path.clone());
+                data.header = header(data);
+                data.status = format!("Moved {} to the trash", entry.path.display());
+                ctx.submit_command(SET_ENTRY.with(self.root_entry(data)));
+            }
+            Err(err) => ctx.submit_command(SET_ERROR.with(format!(
+                "Error: {}: {}",
+                entry.path.display(),
+                err
+            ))),
+        }
+    }
+
+    // Leaves `entry` out of the next scans and out of the tree right away, its
+    // size goes to the excluded paths when they are counted.
+    fn exclude(&mut self, ctx: &mut druid::EventCtx, data: &mut AppState, entry: &Entry) {
+        let mut tree = self.tree.lock().unwrap();
+        let root = tree.path(Tree::ROOT);
+        let relative = match entry.path.strip_prefix(&root) {
+            Ok(relative) => relative,
+            Err(_) => return,
+        };
+        let pattern = exclude_pattern(relative);
+        self.options.excludes.push(pattern.clone());
+        self.scanned_with.excludes.push(pattern);
+
+        let size = tree.size(entry.node);
+        tree.detach(entry.node);
+        if self.options.count_excluded {
+            let excluded = tree
+                .children(Tree::ROOT)
+                .find(|child| tree.kind(*child) == Kind::Excluded);
+            let excluded = excluded.unwrap_or_else(|| {
+                tree.add(
+                    Tree::ROOT,
+                    OsStr::new("(excluded)"),
+                    Kind::Excluded,
+                    Size::default(),
+                    0,
+                )
+            });
+            tree.grow(excluded, size);
+        }
+        drop(tree);
+        data.status = format!("{} is left out of the scans", entry.path.display());
+        ctx.submit_command(SET_ENTRY.with(self.root_entry(data)));
+    }
+
+    // The entry stays on the list when it cannot be put back, to try again.
+    fn undo(&mut self, ctx: &mut druid::EventCtx, data: &mut AppState) {
+        if let Some((_, _, trashed)) = self.trashed.last() {
+            match trash::restore(trashed) {
+                Ok(()) => {
+                    let (parent, node, trashed) = self.trashed.pop().unwrap();
+                    let mut tree = self.tree.lock().unwrap();
+                    // The directory may have been rescanned or left out since.
+                    data.status = match parent {
+                        Some(parent) if tree.is_attached(parent) => {
+                            tree.attach(parent, node);
+                            format!("Put back {}", trashed.original.display())
+                        }
+                        _ => format!("Put back {}, rescan to see it", trashed.original.display()),
+                    };
+                    drop(tree);
+                    ctx.submit_command(SET_ENTRY.with(self.root_entry(data)));
+                }
+                Err(err) => ctx.submit_command(SET_ERROR.with(format!(
+                    "Error: {}: {}",
+                    trashed.original.display(),
+                    err
+                ))),
+            }
+            data.restorable = self
+                .trashed
+                .last()
+                .map(|(_, _, trashed)| trashed.original.clone());
+            data.header = header(data);
+        }
+    }
+
+    // Scans the directory of `entry` again into a new node that takes the
+    // place of the old one, the sizes of the ancestors follow. The old nodes
+    // leave the tree, but those that can be put back from the trash.
+    fn rescan(&mut self, sink: ExtEventSink, entry: &Entry) {
+        let (node, ids) = {
+            let mut tree = self.tree.lock().unwrap();
+            let parent = tree.detach(entry.node);
+            let name = tree.name(entry.node).to_os_string();
+            let kind = tree.kind(entry.node);
+            let mtime = tree.mtime(entry.node);
+            let keep: Vec<NodeId> = self.trashed.iter().map(|(_, node, _)| *node).collect();
+            let ids = tree.compact(&keep);
+            let parent = ids[parent as usize].unwrap();
+            (tree.add(parent, &name, kind, Size::default(), mtime), ids)
+        };
+        for (parent, node, _) in &mut self.trashed {
+            *parent = parent.and_then(|parent| ids[parent as usize]);
+            *node = ids[*node as usize].unwrap();
+        }
+        self.hard_links.lock().unwrap().renumber(&ids);
+        self.start_worker(sink, self.tree.clone(), node);
+    }
+
+    // Asks first, the entry is gone for good.
+    fn delete(&self, sink: ExtEventSink, entry: Arc<Entry>) {
+        std::thread::spawn(move || {
+            let message = format!(
+                "Delete {} permanently? It will not go to the trash.",
+                entry.path.display()
+            );
+            let answer = tinyfiledialogs::message_box_yes_no(
+                "Rustitude",
+                &message,
+                tinyfiledialogs::MessageBoxIcon::Warning,
+                tinyfiledialogs::YesNo::No,
+            );
+            if answer != tinyfiledialogs::YesNo::Yes {
+                return;
+            }
+            let result = std::fs::symlink_metadata(&entry.path).and_then(|metadata| {
+                if metadata.is_dir() {
+                    std::fs::remove_dir_all(&entry.path)
+                } else {
+                    std::fs::remove_file(&entry.path)
+                }
+            });
+            match result {
+                Ok(()) => sink
+                    .submit_command(NOTIFY_DELETED, entry, Target::Auto)
+                    .unwrap(),
+                Err(err) => sink
+                    .submit_command(
+                        SET_ERROR,
+                        format!("Error: {}: {}", entry.path.display(), err),
+                        Target::Auto,
+                    )
+                    .unwrap(),
+            }
+        });
+    }
+
+    // The diff is never locked while holding the tree, nor the other way
+    // around, the comparison thread takes both.
+    fn root_entry(&self, data: &AppState) -> Arc<Entry> {
+        let diff = self.diff.lock().unwrap();
+        if let Some(diff) = &*diff {
+            return root_entry(&diff.tree, Some(diff), data);
+        }
+        drop(diff);
+        root_entry(&self.tree.lock().unwrap(), None, data)
     }
 }
 
@@ -323,12 +1371,114 @@ impl Widget<AppState> for Updater {
             Event::Command(cmd) => {
                 if let Some(_value) = cmd.get(REQUEST_SCAN) {
                     self.stop_worker();
-                    self.start_worker(ctx.get_external_handle(), data.current_dir.clone());
+                    self.start_scan(ctx.get_external_handle(), data);
+                    let path = data.current_dir.clone();
+                    set_scanning(data, &path);
                     let title = format!("Rustitude - {}", data.current_dir.display());
                     ctx.window().set_title(title.as_str());
                 } else if let Some(_value) = cmd.get(REQUEST_REFRESH) {
                     self.stop_worker();
-                    self.start_worker(ctx.get_external_handle(), data.current_dir.clone());
+                    self.start_scan(ctx.get_external_handle(), data);
+                    let path = data.current_dir.clone();
+                    set_scanning(data, &path);
+                } else if let Some(entry) = cmd.get(REQUEST_RESCAN) {
+                    if self.is_current(data, entry) {
+                        self.stop_worker();
+                        self.rescan(ctx.get_external_handle(), entry);
+                        set_scanning(data, &entry.path);
+                    }
+                } else if let Some(node) = cmd.get(REQUEST_SCAN_NODE) {
+                    // A snapshot is not spliced with what is on disk now.
+                    if is_live(data) {
+                        self.stop_worker();
+                        self.start_worker(ctx.get_external_handle(), self.tree.clone(), *node);
+                        set_scanning(data, &self.tree.lock().unwrap().path(*node));
+                    }
+                } else if let Some(path) = cmd.get(REQUEST_LOAD_SNAPSHOT) {
+                    self.stop_worker();
+                    self.start_loader(ctx.get_external_handle(), path.clone());
+                } else if let Some((root, info)) = cmd.get(SET_SNAPSHOT) {
+                    self.trashed.clear();
+                    self.hard_links = Arc::new(Mutex::new(dir::HardLinks::default()));
+                    self.snapshot_deduplicated = info.deduplicated;
+                    self.scanned_at = info.scanned_at;
+                    self.scanned_with = info.options.clone();
+                    let title = format!("Rustitude - {} (snapshot)", root.display());
+                    ctx.window().set_title(title.as_str());
+                } else if let Some(_) = cmd.get(REQUEST_SAVE_SNAPSHOT) {
+                    if data.scanning_dir.is_none() {
+                        self.save(ctx.get_external_handle(), data);
+                    }
+                } else if let Some(_) = cmd.get(REQUEST_OPEN_SNAPSHOT_DIALOG) {
+                    let handle = ctx.get_external_handle();
+                    std::thread::spawn(move || {
+                        if let Some(path) = open_snapshot_dialog() {
+                            handle
+                                .submit_command(REQUEST_LOAD_SNAPSHOT, path, Target::Auto)
+                                .unwrap();
+                        }
+                    });
+                } else if cmd.is(REQUEST_COMPARE_DIALOG) {
+                    let handle = ctx.get_external_handle();
+                    std::thread::spawn(move || {
+                        if let Some(path) = open_snapshot_dialog() {
+                            handle
+                                .submit_command(REQUEST_COMPARE, path, Target::Auto)
+                                .unwrap();
+                        }
+                    });
+                } else if let Some(path) = cmd.get(REQUEST_COMPARE) {
+                    self.stop_worker();
+                    self.start_comparison(ctx.get_external_handle(), path.clone());
+                } else if cmd.is(REQUEST_STOP_COMPARING) {
+                    *self.diff.lock().unwrap() = None;
+                    ctx.submit_command(SET_ENTRY.with(self.root_entry(data)));
+                } else if cmd.is(REQUEST_SHOW_GROWERS) {
+                    data.growers = Arc::new(self.growers(data));
+                    let window = WindowDesc::new(growers_ui())
+                        .window_size((720.0, 360.0))
+                        .title("Rustitude - Top growers");
+                    ctx.new_window(window);
+                } else if cmd.is(REQUEST_SHOW_SETTINGS) {
+                    let window = WindowDesc::new(settings_ui())
+                        .window_size((360.0, 160.0))
+                        .title("Rustitude - Settings");
+                    ctx.new_window(window);
+                } else if let Some(entry) = cmd.get(REQUEST_SHOW_OTHERS) {
+                    data.others = Arc::new(self.others(data, entry));
+                    show_others_page(data, 0);
+                    let window = WindowDesc::new(others_ui())
+                        .window_size((720.0, 360.0))
+                        .title(format!(
+                            "Rustitude - Other items in {}",
+                            entry.path.display()
+                        ));
+                    ctx.new_window(window);
+                } else if let Some(entry) = cmd.get(REQUEST_TRASH) {
+                    if can_delete(entry) && self.is_current(data, entry) {
+                        self.trash(ctx, data, entry);
+                    }
+                } else if let Some(entry) = cmd.get(REQUEST_EXCLUDE) {
+                    if self.is_current(data, entry) {
+                        self.exclude(ctx, data, entry);
+                    }
+                } else if cmd.is(REQUEST_UNDO) {
+                    self.undo(ctx, data);
+                } else if let Some(entry) = cmd.get(REQUEST_DELETE) {
+                    if can_delete(entry) && self.is_current(data, entry) {
+                        self.delete(ctx.get_external_handle(), entry.clone());
+                    }
+                } else if let Some(entry) = cmd.get(NOTIFY_DELETED) {
+                    // Unless the tree was scanned again in the meantime.
+                    if self.is_current(data, entry) {
+                        self.tree.lock().unwrap().detach(entry.node);
+                        data.status = format!("Deleted {}", entry.path.display());
+                        ctx.submit_command(SET_ENTRY.with(self.root_entry(data)));
+                    }
+                } else if let Some(_) = cmd.get(SET_SCANNING) {
+                    ctx.submit_command(SET_ENTRY.with(self.root_entry(data)));
+                } else if let Some(_) = cmd.get(NOTIFY_SCAN_FINISH) {
+                    ctx.submit_command(SET_ENTRY.with(self.root_entry(data)));
                 } else if let Some(_) = cmd.get(REQUEST_OPEN_DIALOG) {
                     let handle = ctx.get_external_handle();
                     let current_dir = data.current_dir.clone();
@@ -357,7 +1507,10 @@ impl Widget<AppState> for Updater {
     ) {
         match event {
             LifeCycle::WidgetAdded => {
-                self.start_worker(ctx.get_external_handle(), data.current_dir.clone());
+                // Empty when a snapshot is opened instead.
+                if !data.current_dir.as_os_str().is_empty() {
+                    self.start_scan(ctx.get_external_handle(), data);
+                }
             }
             _ => {}
         }
@@ -365,11 +1518,19 @@ impl Widget<AppState> for Updater {
 
     fn update(
         &mut self,
-        _ctx: &mut druid::UpdateCtx,
-        _old_data: &AppState,
-        _data: &AppState,
+        ctx: &mut druid::UpdateCtx,
+        old_data: &AppState,
+        data: &AppState,
         _env: &Env,
     ) {
+        // The entries are made again from the tree, nothing is rescanned.
+        if old_data.size_mode != data.size_mode
+            || old_data.coloring != data.coloring
+            || old_data.max_count != data.max_count
+            || old_data.max_depth != data.max_depth
+        {
+            ctx.submit_command(SET_ENTRY.with(self.root_entry(data)));
+        }
     }
 
     fn layout(
@@ -385,12 +1546,475 @@ impl Widget<AppState> for Updater {
     fn paint(&mut self, _ctx: &mut PaintCtx, _data: &AppState, _env: &Env) {}
 }
 
+// Matches `relative`, a path below the root of the scan, and nothing else.
+fn exclude_pattern(relative: &Path) -> String {
+    let mut pattern = String::new();
+    for component in relative.components() {
+        pattern.push('/');
+        for c in component.as_os_str().to_string_lossy().chars() {
+            if "\\*?[]".contains(c) {
+                pattern.push('\\');
+            }
+            pattern.push(c);
+        }
+    }
+    if pattern.ends_with(' ') {
+        pattern.insert(pattern.len() - 1, '\\');
+    }
+    pattern
+}
+
+// The directory at `path`, for nodes that were scanned again.
+fn find_dir(entry: &Arc<Entry>, path: &Path) -> Option<Arc<Entry>> {
+    if entry.path == path && !is_others(entry) {
+        return Some(entry.clone());
+    }
+    entry
+        .children
+        .iter()
+        .filter(|child| path.starts_with(&child.path))
+        .find_map(|child| find_dir(child, path))
+}
+
+fn find_entry(entry: &Arc<Entry>, node: NodeId) -> Option<Arc<Entry>> {
+    if entry.node == node {
+        return Some(entry.clone());
+    }
+    entry
+        .children
+        .iter()
+        .find_map(|child| find_entry(child, node))
+}
+
+fn is_deleted(entry: &Entry) -> bool {
+    entry.change.is_some_and(|change| change.is_deleted())
+}
+
+// Whether `entry` is a single file or directory that can be deleted from the
+// disk, mount points are left alone.
+fn can_delete(entry: &Entry) -> bool {
+    !is_others(entry)
+        && !is_deleted(entry)
+        && entry.kind != Kind::Excluded
+        && entry.kind != Kind::MountPoint
+}
+
+// Whether the shown tree is a finished scan of the disk, the one the actions on
+// entries apply to. Node ids are those of the diff when comparing, and a
+// snapshot may not match what is on disk now.
+fn is_live(data: &AppState) -> bool {
+    data.scanning_dir.is_none() && data.compared.is_none() && data.scanned_at.is_none()
+}
+
+// The actions on `entry`, `zoom` goes into it or out of it.
+fn context_menu(entry: &Arc<Entry>, data: &AppState, zoom: MenuItem<AppState>) -> Menu<AppState> {
+    let on_disk = !is_others(entry) && !is_deleted(entry) && entry.kind != Kind::Excluded;
+    let idle = is_live(data);
+    let rescan = if is_unscanned_mount(entry) {
+        REQUEST_SCAN_NODE.with(entry.node)
+    } else if entry.node == Tree::ROOT {
+        REQUEST_REFRESH.with(())
+    } else {
+        REQUEST_RESCAN.with(entry.clone())
+    };
+    Menu::new("")
+        .entry(
+            MenuItem::new("Open")
+                .command(REQUEST_OPEN.with(entry.path.clone()))
+                .enabled(on_disk),
+        )
+        .entry(
+            MenuItem::new("Reveal in file manager")
+                .command(REQUEST_REVEAL.with(entry.path.clone()))
+                .enabled(on_disk),
+        )
+        .separator()
+        .entry(
+            MenuItem::new("Copy path")
+                .command(REQUEST_COPY.with(entry.path.display().to_string()))
+                .enabled(on_disk),
+        )
+        .entry(
+            MenuItem::new("Copy size")
+                .command(REQUEST_COPY.with(entry_size(entry, data.size_mode))),
+        )
+        .separator()
+        .entry(zoom)
+        .entry(
+            MenuItem::new("Rescan this directory")
+                .command(rescan)
+                .enabled(idle && on_disk && entry.kind != Kind::File),
+        )
+        .entry(
+            MenuItem::new("Exclude from scan")
+                .command(REQUEST_EXCLUDE.with(entry.clone()))
+                .enabled(idle && on_disk && entry.node != Tree::ROOT),
+        )
+        .separator()
+        .entry(
+            MenuItem::new("Move to Trash")
+                .command(REQUEST_TRASH.with(entry.clone()))
+                .enabled(idle && can_delete(entry)),
+        )
+        .entry(
+            MenuItem::new("Delete permanently\u{2026}")
+                .command(REQUEST_DELETE.with(entry.clone()))
+                .enabled(idle && can_delete(entry)),
+        )
+}
+
+// The delta when comparing, the size otherwise.
+fn entry_size(entry: &Entry, mode: SizeMode) -> String {
+    match entry.change {
+        Some(change) => format_delta(change.delta(mode)),
+        None => format_size(entry.size),
+    }
+}
+
+fn describe_change(change: &diff::Change, mode: SizeMode) -> String {
+    if change.is_added() {
+        format!("added, {}", format_size(change.new_size(mode)))
+    } else if change.is_deleted() {
+        format!("deleted, was {}", format_size(change.old_size(mode)))
+    } else {
+        format!(
+            "{}, from {} to {}",
+            format_delta(change.delta(mode)),
+            format_size(change.old_size(mode)),
+            format_size(change.new_size(mode))
+        )
+    }
+}
+
+// Grey when unchanged, shades of green for growth and of red for shrinkage.
+fn change_color(change: &diff::Change, mode: SizeMode) -> Color {
+    const UNCHANGED: (u8, u8, u8) = (0xe8, 0xe8, 0xe8);
+    const GROWN: (u8, u8, u8) = (0x4c, 0xb8, 0x64);
+    const SHRUNK: (u8, u8, u8) = (0xe0, 0x5a, 0x50);
+
+    let delta = change.delta(mode);
+    let (color, amount) = if change.is_added() {
+        (GROWN, 1.0)
+    } else if change.is_deleted() {
+        (SHRUNK, 1.0)
+    } else if delta > 0 {
+        (GROWN, delta as f64 / change.new_size(mode) as f64)
+    } else if delta < 0 {
+        (SHRUNK, -delta as f64 / change.old_size(mode) as f64)
+    } else {
+        (UNCHANGED, 0.0)
+    };
+    // Even a small change stands out from the unchanged entries.
+    let amount = if amount > 0.0 {
+        0.3 + 0.7 * amount
+    } else {
+        0.0
+    };
+    let mix = |from: u8, to: u8| (from as f64 + (to as f64 - from as f64) * amount) as u8;
+    Color::rgb8(
+        mix(UNCHANGED.0, color.0),
+        mix(UNCHANGED.1, color.1),
+        mix(UNCHANGED.2, color.2),
+    )
+}
+
+// The fill of an entry that is not hovered, in either view.
+fn fill_color(entry: &Entry, data: &AppState) -> Color {
+    if is_others(entry) {
+        return Color::from_rgba32_u32(0xe4e4e4ff);
+    }
+    if let Some(change) = &entry.change {
+        return change_color(change, data.size_mode);
+    }
+    match data.coloring {
+        Coloring::Kind => {}
+        Coloring::Category => return category_color(entry, &data.categories),
+        Coloring::Modified => return shade(entry, age_color(entry.modified)),
+        Coloring::Accessed => return shade(entry, age_color(entry.accessed)),
+    }
+    Color::from_rgba32_u32(match entry.kind {
+        Kind::Dir => 0x4faaffff,
+        Kind::File => 0xc4e0ffff,
+        Kind::Symlink => 0xd8ccffff,
+        Kind::MountPoint => 0xd0d0d0ff,
+        Kind::Excluded => 0xf0f0f0ff,
+    })
+}
+
+fn category_color(entry: &Entry, categories: &Categories) -> Color {
+    let color = match entry.category {
+        Some(id) => categories.get(id).color,
+        None => UNKNOWN_COLOR,
+    };
+    shade(entry, color)
+}
+
+// Where `time` falls between the stops of AGE_STOPS, on a log scale.
+fn age_color(time: u32) -> (u8, u8, u8) {
+    if time == 0 {
+        return UNKNOWN_COLOR;
+    }
+    let age = (snapshot::now().saturating_sub(time as u64) as f64)
+        .max(1.0)
+        .ln();
+    let first = AGE_STOPS[0];
+    if age <= (first.0 as f64).ln() {
+        return first.1;
+    }
+    for stops in AGE_STOPS.windows(2) {
+        let (from, to) = ((stops[0].0 as f64).ln(), (stops[1].0 as f64).ln());
+        if age <= to {
+            let amount = (age - from) / (to - from);
+            let (a, b) = (stops[0].1, stops[1].1);
+            let mix = |from: u8, to: u8| (from as f64 + (to as f64 - from as f64) * amount) as u8;
+            return (mix(a.0, b.0), mix(a.1, b.1), mix(a.2, b.2));
+        }
+    }
+    AGE_STOPS[AGE_STOPS.len() - 1].1
+}
+
+// Directories are lighter than the files.
+fn shade(entry: &Entry, (r, g, b): (u8, u8, u8)) -> Color {
+    if entry.kind == Kind::File {
+        Color::rgb8(r, g, b)
+    } else {
+        let tint = |value: u8| value + (0xff - value) / 2;
+        Color::rgb8(tint(r), tint(g), tint(b))
+    }
+}
+
+// A mount point the scanner stopped at, see `ScanOptions::one_file_system`.
+fn is_unscanned_mount(entry: &Entry) -> bool {
+    entry.kind == Kind::MountPoint && entry.children.is_empty() && entry.size == 0
+}
+
 struct Segment {
     entry: Arc<Entry>,
     circle_segment: CircleSegment,
     // path: String,
-    is_dir: bool,
+    depth: usize,
+    // Laid out by `Chart::label_segments`, only drawn on paint.
+    label: Option<SegmentLabel>,
+}
+
+// Text centered on `center`, turned by `rotation`.
+struct SegmentLabel {
+    layout: PietTextLayout,
+    center: druid::Vec2,
+    rotation: f64,
+}
+
+fn draw_label(ctx: &mut PaintCtx, label: &SegmentLabel) {
+    let size = label.layout.size();
+    ctx.with_save(|ctx| {
+        ctx.transform(
+            druid::Affine::translate(label.center) * druid::Affine::rotate(label.rotation),
+        );
+        ctx.draw_text(
+            &label.layout,
+            Point::new(-size.width / 2.0, -size.height / 2.0),
+        );
+    });
+}
+
+// `label` in the font of the labels, cut short with an ellipsis to fit `width`.
+fn fit_label(text: &mut PietText, label: &str, width: f64) -> Option<PietTextLayout> {
+    let mut build = |label: String| {
+        text.new_text_layout(label)
+            .text_color(Color::from_rgba32_u32(0x000000ff))
+            .font(druid::FontFamily::SYSTEM_UI, LABEL_SIZE)
+            .build()
+            .unwrap()
+    };
+    let layout = build(label.to_string());
+    if layout.size().width <= width {
+        return Some(layout);
+    }
+    // The longest prefix that fits, in a few layouts rather than one for
+    // each character dropped.
+    let chars: Vec<char> = label.chars().collect();
+    let (mut low, mut high) = (2, chars.len().saturating_sub(1));
+    let mut fitted = None;
+    while low <= high {
+        let count = (low + high) / 2;
+        let layout = build(chars[..count].iter().collect::<String>() + "\u{2026}");
+        if layout.size().width <= width {
+            fitted = Some(layout);
+            low = count + 1;
+        } else {
+            high = count - 1;
+        }
+    }
+    fitted
+}
+
+// `label` in the middle of `segment`, along the arc or along the radius,
+// whichever has more room, and never upside down.
+fn segment_label(
+    text: &mut PietText,
+    segment: &CircleSegment,
+    label: &str,
+) -> Option<SegmentLabel> {
+    use std::f64::consts::PI;
+
+    let outer = segment.outer_radius;
+    let inner = segment.inner_radius;
+    let angle = segment.start_angle + segment.sweep_angle / 2.0;
+    let arc = inner * segment.sweep_angle;
+    let depth = outer - inner;
+    let (width, height, rotation) = if arc >= depth {
+        // The arc runs left to right at the top, and right to left at the
+        // bottom where the angles are between 0 and PI.
+        let rotation = if angle < PI {
+            angle - PI / 2.0
+        } else {
+            angle + PI / 2.0
+        };
+        (arc, depth, rotation)
+    } else {
+        let rotation = if angle > PI / 2.0 && angle < PI * 1.5 {
+            angle + PI
+        } else {
+            angle
+        };
+        (depth, arc, rotation)
+    };
+    if height < LABEL_SIZE * 1.4 {
+        return None;
+    }
+    let layout = fit_label(text, label, width - 4.0)?;
+
+    let radius = (outer + inner) / 2.0;
+    Some(SegmentLabel {
+        layout,
+        center: druid::Vec2::new(radius * angle.cos(), radius * angle.sin()),
+        rotation,
+    })
+}
+
+struct Tile {
+    entry: Arc<Entry>,
+    rect: Rect,
+}
+
+// The largest aspect ratio of the tiles of `row` laid along `side`.
+fn worst_ratio(row: &[(&Arc<Entry>, f64)], side: f64) -> f64 {
+    let sum: f64 = row.iter().map(|(_, area)| area).sum();
+    let max = row.iter().map(|(_, area)| *area).fold(0.0, f64::max);
+    let min = row
+        .iter()
+        .map(|(_, area)| *area)
+        .fold(f64::INFINITY, f64::min);
+    (side * side * max / (sum * sum)).max(sum * sum / (side * side * min))
+}
+
+// Lays `row` along the shorter side of `rect` and returns what is left of it.
+fn place_row(row: &[(&Arc<Entry>, f64)], rect: Rect, min_area: f64, tiles: &mut Vec<Tile>) -> Rect {
+    let sum: f64 = row.iter().map(|(_, area)| area).sum();
+    let mut pos = 0.0;
+    if rect.width() >= rect.height() {
+        let width = sum / rect.height();
+        for (entry, area) in row {
+            let height = area / width;
+            let tile = Rect::new(
+                rect.x0,
+                rect.y0 + pos,
+                rect.x0 + width,
+                rect.y0 + pos + height,
+            );
+            add_tile(entry, tile, min_area, tiles);
+            pos += height;
+        }
+        Rect::new(rect.x0 + width, rect.y0, rect.x1, rect.y1)
+    } else {
+        let height = sum / rect.width();
+        for (entry, area) in row {
+            let width = area / height;
+            let tile = Rect::new(
+                rect.x0 + pos,
+                rect.y0,
+                rect.x0 + pos + width,
+                rect.y0 + height,
+            );
+            add_tile(entry, tile, min_area, tiles);
+            pos += width;
+        }
+        Rect::new(rect.x0, rect.y0 + height, rect.x1, rect.y1)
+    }
+}
+
+fn add_tile(entry: &Arc<Entry>, rect: Rect, min_area: f64, tiles: &mut Vec<Tile>) {
+    tiles.push(Tile {
+        entry: entry.clone(),
+        rect,
+    });
+    let inner = rect.inset(-TILE_PADDING);
+    if !entry.children.is_empty() && inner.area() >= MIN_TILE_AREA {
+        create_tiles(entry, inner, min_area, tiles);
+    }
+}
+
+// Squarified treemap of the children of `entry`, parents come before their
+// children in `tiles`. What the children leave of the size stays empty, as
+// in the sunburst. Tiles smaller than `min_area` are grouped.
+fn create_tiles(entry: &Entry, rect: Rect, min_area: f64, tiles: &mut Vec<Tile>) {
+    let total = entry.size as f64;
+    let mounts = entry
+        .children
+        .iter()
+        .filter(|v| is_unscanned_mount(v))
+        .count();
+    let reserved = (mounts as f64 * MOUNT_SWEEP_SIZE / (2.0 * std::f64::consts::PI)).min(0.5);
+
+    let (mut items, small): (Vec<(&Arc<Entry>, f64)>, Vec<_>) = entry
+        .children
+        .iter()
+        .map(|v| {
+            let share = if is_unscanned_mount(v) {
+                reserved / mounts as f64
+            } else if total == 0.0 {
+                0.0
+            } else {
+                v.size as f64 / total * (1.0 - reserved)
+            };
+            (v, share * rect.area())
+        })
+        .partition(|(v, area)| *area >= min_area && !is_others(v));
+    // The small ones share a tile, unscanned mount points are left out.
+    let small_area: f64 = small.iter().map(|(_, area)| area).sum();
+    let others = fold_others(
+        entry,
+        small
+            .iter()
+            .map(|(v, _)| *v)
+            .filter(|v| !is_unscanned_mount(v)),
+    );
+    if small_area >= min_area {
+        items.push((&others, small_area));
+    }
+    items.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    let mut rect = rect;
+    let mut row = Vec::new();
+    for item in items {
+        let side = rect.width().min(rect.height());
+        if !row.is_empty() {
+            let before = worst_ratio(&row, side);
+            row.push(item);
+            if worst_ratio(&row, side) <= before {
+                continue;
+            }
+            row.pop();
+            rect = place_row(&row, rect, min_area, tiles);
+            row.clear();
+        }
+        row.push(item);
+    }
+    if !row.is_empty() {
+        place_row(&row, rect, min_area, tiles);
+    }
 }
+
 struct Chart {
     size: String,
     cursor: Point,
@@ -398,6 +2022,12 @@ struct Chart {
     hovered_center: bool,
     expand: VecDeque<Arc<Entry>>,
     segments: Vec<Segment>,
+    chart_size: druid::Size,
+    center_radius: f64,
+    // The treemap is laid out on paint, it depends on the size of the widget.
+    shown: Option<Arc<Entry>>,
+    tiles: Vec<Tile>,
+    tiles_rect: Rect,
     accept: bool,
 }
 
@@ -410,118 +2040,188 @@ impl Chart {
             hovered_center: false,
             expand: VecDeque::new(),
             segments: Vec::new(),
+            chart_size: druid::Size::ZERO,
+            center_radius: 0.0,
+            shown: None,
+            tiles: Vec::new(),
+            tiles_rect: Rect::ZERO,
             accept: false,
         }
     }
 
-    fn refresh_segments(&mut self, entry: Arc<Entry>) {
-        const OUTER: f64 = 60.0;
-        const INNER: f64 = 40.0;
+    fn refresh_segments(&mut self, text: &mut PietText, entry: Arc<Entry>, data: &AppState) {
         const START: f64 = 0.0;
         const END: f64 = 2.0 * std::f64::consts::PI;
-        self.segments = self.create_segments_recursive(entry.clone(), OUTER, INNER, START, END);
+        self.segments = self.create_segments_recursive(entry.clone(), 0, data, START, END);
+        self.place_segments(text, data);
+        self.shown = Some(entry);
+        self.tiles.clear();
+        self.tiles_rect = Rect::ZERO;
     }
 
     fn create_segments_recursive(
         &mut self,
         entry: Arc<Entry>,
-        outer: f64,
-        inner: f64,
+        depth: usize,
+        data: &AppState,
         start: f64,
         end: f64,
     ) -> Vec<Segment> {
+        let min_sweep = data.min_segment.to_radians();
         let total: u64 = entry.size;
         let mut result: Vec<Segment> = Vec::new();
         let mut pos: f64 = start;
+        let mut small: Vec<&Arc<Entry>> = Vec::new();
+        let mut small_sweep = 0.0;
+
+        // Unscanned mount points have no size, they get a fixed slice so that
+        // they can be seen and clicked.
+        let mounts = entry
+            .children
+            .iter()
+            .filter(|v| is_unscanned_mount(v))
+            .count();
+        let reserved = (mounts as f64 * MOUNT_SWEEP_SIZE).min((end - start) / 2.0);
 
         for v in entry.children.iter() {
-            let sweep = v.size as f64 / total as f64 * (end - start);
-            if sweep < MIN_SWEEP_SIZE {
+            let sweep = if is_unscanned_mount(v) {
+                reserved / mounts as f64
+            } else if total == 0 {
+                0.0
+            } else {
+                v.size as f64 / total as f64 * (end - start - reserved)
+            };
+            if sweep < min_sweep || is_others(v) {
+                if !is_unscanned_mount(v) {
+                    small.push(v);
+                    small_sweep += sweep;
+                }
                 continue;
             }
 
+            // The radii are set by place_segments.
             let circle_segment =
-                CircleSegment::new(druid::Point::new(0.0, 0.0), outer, inner, pos, sweep);
+                CircleSegment::new(druid::Point::new(0.0, 0.0), 0.0, 0.0, pos, sweep);
             result.push(Segment {
                 entry: v.clone(),
                 // path: v.path.to_str().unwrap().into(),
-                is_dir: v.path.is_dir(),
                 circle_segment: circle_segment,
+                depth,
+                label: None,
             });
 
-            if !v.children.is_empty() {
-                let mut children: Vec<Segment> = self.create_segments_recursive(
-                    v.clone(),
-                    outer + 20.0,
-                    inner + 20.0,
-                    pos,
-                    pos + sweep,
-                );
+            if !v.children.is_empty() && depth + 1 < data.max_rings {
+                let mut children: Vec<Segment> =
+                    self.create_segments_recursive(v.clone(), depth + 1, data, pos, pos + sweep);
                 result.append(&mut children);
             }
 
             pos += sweep;
         }
 
+        // What is too small to be seen shares one segment at the end.
+        if small_sweep >= min_sweep.max(MIN_VISIBLE_SWEEP) {
+            let circle_segment =
+                CircleSegment::new(druid::Point::new(0.0, 0.0), 0.0, 0.0, pos, small_sweep);
+            result.push(Segment {
+                entry: fold_others(&entry, small.into_iter()),
+                circle_segment,
+                depth,
+                label: None,
+            });
+        }
+
         return result;
     }
 
-    fn is_hovered_center(&self) -> bool {
-        return self.hovered_center;
+    // Fits the visible rings in the chart, the center is as wide as two rings.
+    fn place_segments(&mut self, text: &mut PietText, data: &AppState) {
+        let rings = self.segments.iter().map(|v| v.depth + 1).max().unwrap_or(1);
+        let radius =
+            (self.chart_size.width.min(self.chart_size.height) / 2.0 - CHART_MARGIN).max(0.0);
+        let ring = radius / (rings + 2) as f64;
+        self.center_radius = ring * 2.0;
+        for v in &mut self.segments {
+            v.circle_segment.inner_radius = self.center_radius + ring * v.depth as f64;
+            v.circle_segment.outer_radius = v.circle_segment.inner_radius + ring;
+        }
+        self.label_segments(text, data);
     }
 
-    fn is_hovered_child(&self) -> bool {
-        return self.hovered_entry.is_some();
+    // Text layouts are slow to build, they are made again only when the
+    // segments or their text change and not on every paint.
+    fn label_segments(&mut self, text: &mut PietText, data: &AppState) {
+        for v in &mut self.segments {
+            let name = v.entry.path.file_name().unwrap_or_default();
+            let name = name.to_string_lossy().to_string();
+            v.label = if is_unscanned_mount(&v.entry) {
+                let layout = text
+                    .new_text_layout(name)
+                    .text_color(Color::from_rgba32_u32(0x000000ff))
+                    .font(druid::FontFamily::SYSTEM_UI, 9.0)
+                    .build()
+                    .unwrap();
+                let segment = &v.circle_segment;
+                let angle = segment.start_angle + segment.sweep_angle / 2.0;
+                let radius = (segment.outer_radius + segment.inner_radius) / 2.0;
+                Some(SegmentLabel {
+                    layout,
+                    center: druid::Vec2::new(radius * angle.cos(), radius * angle.sin()),
+                    rotation: 0.0,
+                })
+            } else if data.label_sizes {
+                let label = format!("{} {}", name, entry_size(&v.entry, data.size_mode));
+                segment_label(text, &v.circle_segment, &label)
+            } else {
+                segment_label(text, &v.circle_segment, &name)
+            };
+        }
     }
 
-    fn format_size(&self, value: u64) -> String {
-        const KB: u64 = 1024;
-        const MB: u64 = KB * 1024;
-        const GB: u64 = MB * 1024;
-        const TB: u64 = GB * 1024;
-        const PB: u64 = TB * 1024;
-
-        let size = value.to_owned();
-        if size < MB {
-            format!("{:.02} KB", size as f64 / KB as f64)
-        } else if size < GB {
-            format!("{:.02} MB", size as f64 / MB as f64)
-        } else if size < TB {
-            format!("{:.02} GB", size as f64 / GB as f64)
-        } else if size < PB {
-            format!("{:.02} TB", size as f64 / TB as f64)
-        } else {
-            format!("{:.02} PB", size as f64 / PB as f64)
-        }
+    fn is_hovered_center(&self) -> bool {
+        return self.hovered_center;
+    }
+
+    fn is_hovered_child(&self) -> bool {
+        return self.hovered_entry.is_some();
     }
 }
 
 impl Widget<AppState> for Chart {
     fn event(&mut self, ctx: &mut druid::EventCtx, event: &Event, data: &mut AppState, _env: &Env) {
         match event {
+            Event::MouseDown(v) if self.accept && v.button.is_left() && v.count == 2 => {
+                if self.is_hovered_center() {
+                    ctx.submit_command(REQUEST_ZOOM_OUT);
+                } else if let Some(entry) = self.hovered_entry.clone() {
+                    if !entry.children.is_empty() {
+                        ctx.submit_command(REQUEST_ZOOM.with(entry));
+                    }
+                }
+            }
             Event::MouseUp(v) => {
                 if self.accept {
                     if v.button.is_left() {
                         if let Some(v) = &self.hovered_entry.as_ref() {
-                            opener::open(&v.path).unwrap();
+                            if is_unscanned_mount(v) && is_live(data) {
+                                ctx.submit_command(REQUEST_SCAN_NODE.with(v.node));
+                            }
                         }
                     } else if v.button.is_right() {
                         if self.is_hovered_center() {
-                            self.expand.pop_front();
-                            let entry = if let Some(entry) = self.expand.front() {
-                                entry.clone()
-                            } else {
-                                data.entry.clone()
-                            };
-                            self.refresh_segments(entry.clone());
-
-                            ctx.request_paint();
-                        } else if self.is_hovered_child() {
-                            self.expand
-                                .push_front(self.hovered_entry.as_ref().unwrap().clone());
-                            self.refresh_segments(self.expand.front().unwrap().clone());
-
-                            ctx.request_paint();
+                            let entry = self.expand.front().unwrap_or(&data.entry).clone();
+                            let zoom = MenuItem::new("Zoom out")
+                                .command(REQUEST_ZOOM_OUT)
+                                .enabled(!self.expand.is_empty());
+                            ctx.show_context_menu(context_menu(&entry, data, zoom), v.window_pos);
+                        } else if self.hovered_entry.as_ref().is_some_and(|v| is_others(v)) {
+                            let entry = self.hovered_entry.clone().unwrap();
+                            ctx.submit_command(REQUEST_SHOW_OTHERS.with(entry));
+                        } else if let Some(entry) = self.hovered_entry.clone() {
+                            let zoom = MenuItem::new("Zoom into")
+                                .command(REQUEST_ZOOM.with(entry.clone()))
+                                .enabled(!entry.children.is_empty());
+                            ctx.show_context_menu(context_menu(&entry, data, zoom), v.window_pos);
                         }
                     }
                 }
@@ -532,30 +2232,51 @@ impl Widget<AppState> for Chart {
                 if self.accept {
                     if self.is_hovered_center() {
                         if let Some(expand) = self.expand.front() {
-                            data.expand = String::from("Right-click to go back");
-                            data.status = format!("Click to locate {}", expand.path.display());
-                            self.size = self.format_size(expand.size);
+                            data.expand = String::from("Double-click to go back");
+                            data.status = expand.path.display().to_string();
+                            self.size = entry_size(expand, data.size_mode);
                         } else {
                             data.expand = String::new();
-                            data.status = format!("Click to locate {}", data.current_dir.display());
-                            self.size = self.format_size(data.entry.size);
+                            data.status = data.current_dir.display().to_string();
+                            self.size = entry_size(&data.entry, data.size_mode);
                         }
                     } else if self.is_hovered_child() {
-                        data.expand = String::from("Right-click to expand");
+                        data.expand = String::from("Double-click to zoom in, right-click for more");
                         if let Some(entry) = self.hovered_entry.clone() {
-                            data.status = format!("Click to locate {}", entry.path.display());
-                            self.size = self.format_size(entry.size);
+                            data.status = if is_others(&entry) {
+                                data.expand = String::from("Right-click to list them");
+                                format!(
+                                    "{} other items in {}",
+                                    entry.others.len(),
+                                    entry.path.display()
+                                )
+                            } else if is_unscanned_mount(&entry) && is_live(data) {
+                                format!(
+                                    "Mount point {} (another file system), click to scan it",
+                                    entry.path.display()
+                                )
+                            } else if entry.kind == Kind::Excluded {
+                                String::from("Paths left out by the exclude patterns")
+                            } else if let Some(change) = entry.change {
+                                let change = describe_change(&change, data.size_mode);
+                                format!("{} ({})", entry.path.display(), change)
+                            } else if entry.kind == Kind::Symlink {
+                                format!("{} (symlink)", entry.path.display())
+                            } else {
+                                entry.path.display().to_string()
+                            };
+                            self.size = entry_size(&entry, data.size_mode);
                         }
                     } else {
                         data.expand = String::new();
 
                         if data.scanning_dir.is_none() {
                             if let Some(expand) = self.expand.front() {
-                                data.status = format!("Scan of {}", expand.path.display());
-                                self.size = self.format_size(expand.size);
+                                data.status = scan_status(data, &expand.path);
+                                self.size = entry_size(expand, data.size_mode);
                             } else {
-                                data.status = format!("Scan of {}", data.current_dir.display());
-                                self.size = self.format_size(data.entry.size);
+                                data.status = scan_status(data, &data.current_dir);
+                                self.size = entry_size(&data.entry, data.size_mode);
                             }
                         }
                     }
@@ -565,14 +2286,53 @@ impl Widget<AppState> for Chart {
             }
             Event::Command(cmd) => {
                 if let Some(entry) = cmd.get(SET_ENTRY) {
-                    self.refresh_segments(entry.clone());
-                    self.size = self.format_size(entry.size);
-                } else if let Some(_) = cmd.get(REQUEST_SCAN) {
+                    // Follow the zoomed entries into the new snapshot.
+                    let mut expand = VecDeque::new();
+                    for zoomed in self.expand.iter().rev() {
+                        // Node ids change when a rescan compacts the tree.
+                        let found = find_entry(entry, zoomed.node)
+                            .filter(|found| found.path == zoomed.path)
+                            .or_else(|| find_dir(entry, &zoomed.path));
+                        match found {
+                            Some(found) => expand.push_front(found),
+                            None => break,
+                        }
+                    }
+                    self.expand = expand;
+
+                    let entry = self.expand.front().unwrap_or(entry).clone();
+                    self.refresh_segments(ctx.text(), entry.clone(), data);
+                    self.size = entry_size(&entry, data.size_mode);
+                } else if let Some(entry) = cmd.get(REQUEST_ZOOM) {
+                    self.expand.push_front(entry.clone());
+                    self.refresh_segments(ctx.text(), entry.clone(), data);
+                    ctx.request_paint();
+                } else if cmd.is(REQUEST_ZOOM_OUT) {
+                    self.expand.pop_front();
+                    let entry = self.expand.front().unwrap_or(&data.entry).clone();
+                    self.refresh_segments(ctx.text(), entry, data);
+                    ctx.request_paint();
+                } else if cmd.is(REQUEST_SCAN) || cmd.is(REQUEST_LOAD_SNAPSHOT) {
                     self.segments.clear();
+                    self.shown = None;
+                    self.tiles.clear();
                     self.expand.clear();
                     self.size.clear();
                     self.hovered_entry = None;
                     self.accept = false;
+                } else if cmd.is(REQUEST_SCAN_NODE)
+                    || cmd.is(REQUEST_RESCAN)
+                    || cmd.is(REQUEST_REFRESH)
+                {
+                    // Node ids of the current entries are only valid until
+                    // the scan is done.
+                    self.accept = false;
+                } else if cmd.is(REQUEST_COMPARE) || cmd.is(REQUEST_STOP_COMPARING) {
+                    // The entries switch between the ids of the tree and of
+                    // the diff.
+                    self.expand.clear();
+                    self.hovered_entry = None;
+                    self.accept = cmd.is(REQUEST_STOP_COMPARING);
                 } else if let Some(_) = cmd.get(NOTIFY_SCAN_FINISH) {
                     self.accept = true
                 }
@@ -592,38 +2352,79 @@ impl Widget<AppState> for Chart {
 
     fn update(
         &mut self,
-        _ctx: &mut druid::UpdateCtx,
-        _old_data: &AppState,
-        _data: &AppState,
+        ctx: &mut druid::UpdateCtx,
+        old_data: &AppState,
+        data: &AppState,
         _env: &Env,
     ) {
+        if old_data.view != data.view || old_data.coloring != data.coloring {
+            ctx.request_paint();
+        }
+        if old_data.label_sizes != data.label_sizes {
+            self.label_segments(ctx.text(), data);
+            ctx.request_paint();
+        }
+        if old_data.max_rings != data.max_rings || old_data.min_segment != data.min_segment {
+            if let Some(shown) = self.shown.clone() {
+                self.refresh_segments(ctx.text(), shown, data);
+                ctx.request_paint();
+            }
+        }
     }
 
     fn layout(
         &mut self,
-        _ctx: &mut druid::LayoutCtx,
+        ctx: &mut druid::LayoutCtx,
         bc: &druid::BoxConstraints,
-        _data: &AppState,
+        data: &AppState,
         _env: &Env,
     ) -> druid::Size {
-        bc.max()
+        let size = bc.max();
+        if size != self.chart_size {
+            self.chart_size = size;
+            self.place_segments(ctx.text(), data);
+        }
+        size
     }
 
     fn paint(&mut self, ctx: &mut PaintCtx, data: &AppState, _env: &Env) {
         let brush_bg = ctx.solid_brush(Color::from_rgba32_u32(0xffffffff));
+        let bounds = ctx.size().to_rect();
+        ctx.fill(bounds, &brush_bg);
+
+        self.hovered_entry = None;
+
+        match data.view {
+            View::Sunburst => self.paint_sunburst(ctx, data),
+            View::Treemap => self.paint_treemap(ctx, data),
+        }
+
+        if self.accept {
+            if let Some(entry) = self.hovered_entry.clone() {
+                self.paint_tooltip(ctx, data, &entry);
+            }
+        }
+    }
+}
+
+impl Chart {
+    fn center_text(&self, data: &AppState) -> String {
+        if data.scanning_dir.is_some() {
+            String::from("Scanning ...")
+        } else {
+            self.size.clone()
+        }
+    }
+
+    fn paint_sunburst(&mut self, ctx: &mut PaintCtx, data: &AppState) {
         let brush_stroke = ctx.solid_brush(Color::from_rgba32_u32(0x101010bc));
         let brush_fill_hovered = ctx.solid_brush(Color::from_rgba32_u32(0x2f6fffff));
         let brush_fill_dir = ctx.solid_brush(Color::from_rgba32_u32(0x4faaffff));
-        let brush_fill_file = ctx.solid_brush(Color::from_rgba32_u32(0xc4e0ffff));
         let text_color = Color::from_rgba32_u32(0x000000ff);
 
         let bounds = ctx.size().to_rect();
-        ctx.fill(bounds, &brush_bg);
-
-        self.hovered_entry = None;
-
         let center = bounds.center();
-        let circle_path = Circle::new(center, 40.0);
+        let circle_path = Circle::new(center, self.center_radius);
         ctx.stroke(&circle_path, &brush_stroke, 1.5);
         if circle_path.contains(self.cursor) {
             self.hovered_center = true;
@@ -639,14 +2440,9 @@ impl Widget<AppState> for Chart {
             ctx.fill(&circle_path, &brush_fill_dir);
         }
 
-        let text = if data.scanning_dir.is_some() {
-            String::from("Scanning ...")
-        } else {
-            self.size.clone()
-        };
         let layout = ctx
             .text()
-            .new_text_layout(text)
+            .new_text_layout(self.center_text(data))
             .text_color(text_color)
             .build()
             .unwrap();
@@ -690,23 +2486,262 @@ impl Widget<AppState> for Chart {
                     self.hovered_entry = Some(v.entry.clone());
                 }
 
-                let fill = if is_hovered {
-                    &brush_fill_hovered
-                } else if v.is_dir {
-                    &brush_fill_dir
+                if is_hovered {
+                    ctx.fill(&v.circle_segment, &brush_fill_hovered);
                 } else {
-                    &brush_fill_file
-                };
-
-                ctx.fill(&v.circle_segment, fill);
+                    ctx.fill(&v.circle_segment, &fill_color(&v.entry, data));
+                }
                 ctx.stroke(&v.circle_segment, &brush_stroke, 1.0);
+
+                if let Some(label) = &v.label {
+                    draw_label(ctx, label);
+                }
             }
         });
     }
+
+    // Details of the hovered entry, next to the cursor.
+    fn paint_tooltip(&self, ctx: &mut PaintCtx, data: &AppState, entry: &Entry) {
+        const PADDING: f64 = 4.0;
+        const OFFSET: f64 = 16.0;
+
+        let percent = |total: u64| {
+            if total == 0 {
+                0.0
+            } else {
+                entry.size as f64 * 100.0 / total as f64
+            }
+        };
+        let mut lines = vec![if is_others(entry) {
+            format!(
+                "{} other items in {}",
+                entry.others.len(),
+                entry.path.display()
+            )
+        } else {
+            entry.path.display().to_string()
+        }];
+        lines.push(format!(
+            "{} apparent, {} allocated",
+            format_size(entry.sizes.apparent),
+            format_size(entry.sizes.allocated)
+        ));
+        if let Some(change) = &entry.change {
+            lines.push(describe_change(change, data.size_mode));
+        }
+        lines.push(format!(
+            "{:.1}% of the parent, {:.1}% of the scan",
+            percent(entry.parent_size),
+            percent(data.entry.size)
+        ));
+        if entry.kind == Kind::Dir || entry.kind == Kind::MountPoint {
+            lines.push(format!(
+                "{} files, {} directories",
+                entry.counts.files, entry.counts.dirs
+            ));
+        }
+        let now = snapshot::now();
+        if entry.mtime != 0 {
+            let age = now.saturating_sub(entry.mtime as u64);
+            lines.push(format!("Modified {} ago", format_age(age)));
+        }
+        if entry.atime != 0 {
+            let age = now.saturating_sub(entry.atime as u64);
+            lines.push(format!("Accessed {} ago", format_age(age)));
+        }
+        if entry.kind != Kind::File && entry.modified != 0 {
+            let age = now.saturating_sub(entry.modified as u64);
+            lines.push(format!("Newest contents modified {} ago", format_age(age)));
+        }
+
+        let layout = ctx
+            .text()
+            .new_text_layout(lines.join("\n"))
+            .text_color(Color::from_rgba32_u32(0x000000ff))
+            .font(druid::FontFamily::SYSTEM_UI, 12.0)
+            .build()
+            .unwrap();
+        let size = layout.size();
+        let width = size.width + PADDING * 2.0;
+        let height = size.height + PADDING * 2.0;
+
+        // Below and right of the cursor, unless it would leave the chart.
+        let bounds = ctx.size().to_rect();
+        let mut x = self.cursor.x + OFFSET;
+        if x + width > bounds.x1 {
+            x = self.cursor.x - OFFSET - width;
+        }
+        let mut y = self.cursor.y + OFFSET;
+        if y + height > bounds.y1 {
+            y = self.cursor.y - OFFSET - height;
+        }
+        let rect = Rect::new(x, y, x + width, y + height);
+        ctx.fill(rect, &Color::from_rgba32_u32(0xffffffee));
+        ctx.stroke(rect, &Color::from_rgba32_u32(0x101010bc), 1.0);
+        ctx.draw_text(&layout, Point::new(x + PADDING, y + PADDING));
+    }
+
+    // The header stands for the shown directory, like the center of the
+    // sunburst.
+    fn paint_treemap(&mut self, ctx: &mut PaintCtx, data: &AppState) {
+        let brush_stroke = ctx.solid_brush(Color::from_rgba32_u32(0x101010bc));
+        let brush_fill_hovered = ctx.solid_brush(Color::from_rgba32_u32(0x2f6fffff));
+        let brush_fill_dir = ctx.solid_brush(Color::from_rgba32_u32(0x4faaffff));
+        let text_color = Color::from_rgba32_u32(0x000000ff);
+
+        let bounds = ctx.size().to_rect();
+        let header = Rect::new(bounds.x0, bounds.y0, bounds.x1, bounds.y0 + TREEMAP_HEADER);
+        let area = Rect::new(bounds.x0, header.y1, bounds.x1, bounds.y1).inset(-TILE_PADDING);
+        if area != self.tiles_rect {
+            self.tiles.clear();
+            if let Some(shown) = &self.shown {
+                // The same share of the whole as the smallest segment.
+                let min_area = (area.area() * data.min_segment / 360.0).max(MIN_TILE_AREA);
+                create_tiles(shown, area, min_area, &mut self.tiles);
+            }
+            self.tiles_rect = area;
+        }
+
+        self.hovered_center = header.contains(self.cursor);
+        if self.hovered_center {
+            self.hovered_entry = Some(self.expand.front().unwrap_or(&data.entry).clone());
+            ctx.fill(header, &brush_fill_hovered);
+        } else {
+            ctx.fill(header, &brush_fill_dir);
+        }
+        let path = self
+            .shown
+            .as_ref()
+            .map_or(PathBuf::new(), |shown| shown.path.clone());
+        let layout = ctx
+            .text()
+            .new_text_layout(format!("{}  {}", path.display(), self.center_text(data)))
+            .text_color(text_color)
+            .build()
+            .unwrap();
+        let pos = Point::new(
+            header.x0 + TILE_PADDING * 2.0,
+            header.y0 + (header.height() - layout.size().height) / 2.0,
+        );
+        ctx.draw_text(&layout, pos);
+
+        // The innermost tile under the cursor is the hovered one.
+        let hovered = self
+            .tiles
+            .iter()
+            .rposition(|tile| tile.rect.contains(self.cursor));
+        if let Some(i) = hovered {
+            self.hovered_entry = Some(self.tiles[i].entry.clone());
+        }
+        for (i, tile) in self.tiles.iter().enumerate() {
+            if Some(i) == hovered {
+                ctx.fill(tile.rect, &brush_fill_hovered);
+            } else {
+                ctx.fill(tile.rect, &fill_color(&tile.entry, data));
+            }
+            ctx.stroke(tile.rect, &brush_stroke, 1.0);
+        }
+    }
+}
+
+// What the colors of the chart mean, unless they show the kinds.
+struct Legend {}
+
+impl Legend {
+    fn items(data: &AppState) -> Vec<((u8, u8, u8), String)> {
+        match data.coloring {
+            Coloring::Kind => Vec::new(),
+            Coloring::Category => data
+                .categories
+                .iter()
+                .map(|category| (category.color, category.name.clone()))
+                .collect(),
+            Coloring::Modified | Coloring::Accessed => {
+                let now = snapshot::now();
+                let mut items: Vec<((u8, u8, u8), String)> = AGE_STOPS
+                    .iter()
+                    .map(|(age, color)| (*color, format_date(now.saturating_sub(*age))))
+                    .collect();
+                items.push((UNKNOWN_COLOR, String::from("unknown")));
+                items
+            }
+        }
+    }
+}
+
+impl Widget<AppState> for Legend {
+    fn event(
+        &mut self,
+        _ctx: &mut druid::EventCtx,
+        _event: &Event,
+        _data: &mut AppState,
+        _env: &Env,
+    ) {
+    }
+
+    fn lifecycle(
+        &mut self,
+        _ctx: &mut druid::LifeCycleCtx,
+        _event: &druid::LifeCycle,
+        _data: &AppState,
+        _env: &Env,
+    ) {
+    }
+
+    fn update(
+        &mut self,
+        ctx: &mut druid::UpdateCtx,
+        old_data: &AppState,
+        data: &AppState,
+        _env: &Env,
+    ) {
+        if old_data.coloring != data.coloring {
+            ctx.request_layout();
+        }
+    }
+
+    fn layout(
+        &mut self,
+        _ctx: &mut druid::LayoutCtx,
+        bc: &druid::BoxConstraints,
+        data: &AppState,
+        _env: &Env,
+    ) -> druid::Size {
+        let height = if data.coloring != Coloring::Kind {
+            20.0
+        } else {
+            0.0
+        };
+        bc.constrain((bc.max().width, height))
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &AppState, _env: &Env) {
+        const CHIP: f64 = 10.0;
+
+        let bounds = ctx.size().to_rect();
+        ctx.fill(bounds, &Color::from_rgba32_u32(0xffffffff));
+
+        let mut x = bounds.x0 + 4.0;
+        let y = bounds.center().y;
+        for ((r, g, b), name) in Legend::items(data) {
+            let chip = Rect::new(x, y - CHIP / 2.0, x + CHIP, y + CHIP / 2.0);
+            ctx.fill(chip, &Color::rgb8(r, g, b));
+            let layout = ctx
+                .text()
+                .new_text_layout(name)
+                .text_color(Color::from_rgba32_u32(0x000000ff))
+                .font(druid::FontFamily::SYSTEM_UI, 12.0)
+                .build()
+                .unwrap();
+            let size = layout.size();
+            ctx.draw_text(&layout, Point::new(chip.x1 + 4.0, y - size.height / 2.0));
+            x = chip.x1 + 4.0 + size.width + 12.0;
+        }
+    }
 }
 
-fn ui_builder() -> impl Widget<AppState> {
-    let updater = Updater::new();
+fn ui_builder(options: dir::ScanOptions) -> impl Widget<AppState> {
+    let updater = Updater::new(options);
 
     let current_dir = Label::new(|data: &AppState, _env: &_| format!("{}", data.header))
         .with_text_color(Color::from_rgba32_u32(0x000000ff))
@@ -739,8 +2774,461 @@ fn ui_builder() -> impl Widget<AppState> {
     col.add_child(updater);
     col.add_child(current_dir);
     col.add_flex_child(paint, 1.0);
+    col.add_child(Legend {});
     col.add_child(expand);
     col.add_child(status);
 
     return col;
 }
+
+fn errors_ui() -> impl Widget<AppState> {
+    let summary = Label::new(|data: &AppState, _env: &_| {
+        format!(
+            "{} paths skipped, their contents are unknown and not counted",
+            data.errors.len()
+        )
+    })
+    .with_text_color(Color::from_rgba32_u32(0x000000ff))
+    .with_text_size(12.0)
+    .background(Color::from_rgba32_u32(0xffffffff))
+    .expand_width();
+
+    let list = Scroll::new(List::new(|| {
+        Label::new(|error: &Arc<dir::ScanError>, _env: &_| {
+            format!(
+                "[{}] {} ({})",
+                error.kind,
+                error.path.display(),
+                error.message
+            )
+        })
+        .with_text_color(Color::from_rgba32_u32(0x000000ff))
+        .with_text_size(12.0)
+        .expand_width()
+    }))
+    .vertical()
+    .lens(AppState::errors)
+    .background(Color::from_rgba32_u32(0xffffffff))
+    .expand();
+
+    let mut col = Flex::column();
+    col.add_child(summary);
+    col.add_flex_child(list, 1.0);
+
+    return col;
+}
+
+// A setting with buttons to step it down and up.
+fn setting_row(
+    text: fn(&AppState) -> String,
+    step: fn(&mut AppState, i32),
+) -> impl Widget<AppState> {
+    let label = Label::new(move |data: &AppState, _env: &_| text(data))
+        .with_text_color(Color::from_rgba32_u32(0x000000ff))
+        .with_text_size(12.0)
+        .expand_width();
+    let less = Label::new(|_data: &AppState, _env: &_| String::from(" - "))
+        .with_text_color(Color::from_rgba32_u32(0x000000ff))
+        .with_text_size(12.0)
+        .on_click(
+            move |_ctx: &mut druid::EventCtx, data: &mut AppState, _env: &Env| step(data, -1),
+        );
+    let more = Label::new(|_data: &AppState, _env: &_| String::from(" + "))
+        .with_text_color(Color::from_rgba32_u32(0x000000ff))
+        .with_text_size(12.0)
+        .on_click(move |_ctx: &mut druid::EventCtx, data: &mut AppState, _env: &Env| step(data, 1));
+
+    let mut row = Flex::row();
+    row.add_flex_child(label, 1.0);
+    row.add_child(less);
+    row.add_child(more);
+    row
+}
+
+fn settings_ui() -> impl Widget<AppState> {
+    let mut col = Flex::column();
+    col.add_child(setting_row(
+        |data| format!("Entries per directory: {}", data.max_count),
+        |data, step| data.max_count = (data.max_count as i64 + step as i64 * 5).max(1) as usize,
+    ));
+    col.add_child(setting_row(
+        |data| format!("Levels of directories: {}", data.max_depth),
+        |data, step| data.max_depth = (data.max_depth as i64 + step as i64).max(0) as usize,
+    ));
+    col.add_child(setting_row(
+        |data| format!("Rings of the chart: {}", data.max_rings),
+        |data, step| data.max_rings = (data.max_rings as i64 + step as i64).max(1) as usize,
+    ));
+    col.add_child(setting_row(
+        |data| format!("Smallest segment: {:.1} degrees", data.min_segment),
+        |data, step| {
+            let angle = data.min_segment + step as f64 * 0.1;
+            data.min_segment = ((angle * 10.0).round() / 10.0).clamp(0.0, 360.0);
+        },
+    ));
+
+    col.background(Color::from_rgba32_u32(0xffffffff)).expand()
+}
+
+fn show_others_page(data: &mut AppState, page: usize) {
+    let start = (page * OTHERS_PAGE_SIZE).min(data.others.len());
+    let end = (start + OTHERS_PAGE_SIZE).min(data.others.len());
+    data.others_page = page;
+    data.others_shown = Arc::new(data.others[start..end].to_vec());
+}
+
+fn others_ui() -> impl Widget<AppState> {
+    let summary = Label::new(|data: &AppState, _env: &_| {
+        let start = data.others_page * OTHERS_PAGE_SIZE;
+        format!(
+            "{} to {} of {} items",
+            start + 1,
+            start + data.others_shown.len(),
+            data.others.len()
+        )
+    })
+    .with_text_color(Color::from_rgba32_u32(0x000000ff))
+    .with_text_size(12.0)
+    .background(Color::from_rgba32_u32(0xffffffff))
+    .expand_width();
+
+    let previous = Label::new(|_data: &AppState, _env: &_| String::from("Previous"))
+        .with_text_color(Color::from_rgba32_u32(0x000000ff))
+        .with_text_size(12.0)
+        .on_click(
+            |_ctx: &mut druid::EventCtx, data: &mut AppState, _env: &Env| {
+                if data.others_page > 0 {
+                    show_others_page(data, data.others_page - 1);
+                }
+            },
+        );
+    let next = Label::new(|_data: &AppState, _env: &_| String::from("Next"))
+        .with_text_color(Color::from_rgba32_u32(0x000000ff))
+        .with_text_size(12.0)
+        .on_click(
+            |_ctx: &mut druid::EventCtx, data: &mut AppState, _env: &Env| {
+                if (data.others_page + 1) * OTHERS_PAGE_SIZE < data.others.len() {
+                    show_others_page(data, data.others_page + 1);
+                }
+            },
+        );
+    let mut pages = Flex::row();
+    pages.add_child(previous);
+    pages.add_child(next);
+    pages.add_flex_child(summary, 1.0);
+
+    let list = Scroll::new(List::new(|| {
+        Label::new(|item: &OtherItem, _env: &_| {
+            format!("{:>12}  {}", format_size(item.size), item.path.display())
+        })
+        .with_text_color(Color::from_rgba32_u32(0x000000ff))
+        .with_text_size(12.0)
+        .expand_width()
+    }))
+    .vertical()
+    .lens(AppState::others_shown)
+    .background(Color::from_rgba32_u32(0xffffffff))
+    .expand();
+
+    let mut col = Flex::column();
+    col.add_child(pages.background(Color::from_rgba32_u32(0xffffffff)));
+    col.add_flex_child(list, 1.0);
+
+    col
+}
+
+fn growers_ui() -> impl Widget<AppState> {
+    let summary = Label::new(|data: &AppState, _env: &_| {
+        format!(
+            "{} paths grew the most, {} in all",
+            data.growers.len(),
+            entry_size(&data.entry, data.size_mode)
+        )
+    })
+    .with_text_color(Color::from_rgba32_u32(0x000000ff))
+    .with_text_size(12.0)
+    .background(Color::from_rgba32_u32(0xffffffff))
+    .expand_width();
+
+    let list = Scroll::new(List::new(|| {
+        Label::new(|grower: &Grower, _env: &_| {
+            format!(
+                "{:>12}  {}{}",
+                format_delta(grower.delta),
+                grower.path.display(),
+                if grower.added { " (added)" } else { "" }
+            )
+        })
+        .with_text_color(Color::from_rgba32_u32(0x000000ff))
+        .with_text_size(12.0)
+        .expand_width()
+    }))
+    .vertical()
+    .lens(AppState::growers)
+    .background(Color::from_rgba32_u32(0xffffffff))
+    .expand();
+
+    let mut col = Flex::column();
+    col.add_child(summary);
+    col.add_flex_child(list, 1.0);
+
+    col
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(current_dir: &Path) -> AppState {
+        let matches = cli::app().get_matches_from(vec!["rustitude"]);
+        let options = dir::ScanOptions::default();
+        initial_state(
+            current_dir.to_path_buf(),
+            &matches,
+            Categories::default(),
+            &options,
+        )
+    }
+
+    #[test]
+    fn acts_only_on_a_tree_scanned_from_disk() {
+        let root = Path::new("/data");
+        let mut tree = Tree::new(root);
+        tree.add(Tree::ROOT, OsStr::new("a"), Kind::Dir, Size::default(), 0);
+        let mut data = state(root);
+        assert!(!is_live(&data));
+        data.scanning_dir = None;
+        data.entry = root_entry(&tree, None, &data);
+        let entry = data.entry.children[0].clone();
+        assert_eq!(entry.path, root.join("a"));
+
+        let updater = Updater::new(dir::ScanOptions::default());
+        *updater.tree.lock().unwrap() = tree;
+        assert!(is_live(&data));
+        assert!(updater.is_current(&data, &entry));
+
+        // Loaded from a snapshot or an ncdu dump, the paths may have changed
+        // on disk since.
+        data.scanned_at = Some(0);
+        assert!(!is_live(&data));
+        assert!(!updater.is_current(&data, &entry));
+
+        data.scanned_at = None;
+        data.scanning_dir = Some(root.to_path_buf());
+        assert!(!is_live(&data));
+        assert!(!updater.is_current(&data, &entry));
+
+        data.scanning_dir = None;
+        data.compared = Some((0, 0));
+        assert!(!is_live(&data));
+        assert!(!updater.is_current(&data, &entry));
+    }
+
+    // Adds a file of `size` bytes under `parent`.
+    fn file(tree: &mut Tree, parent: NodeId, name: &str, size: u64) -> NodeId {
+        let size = Size {
+            apparent: size,
+            allocated: size,
+        };
+        let id = tree.add(parent, OsStr::new(name), Kind::File, Size::default(), 0);
+        tree.grow(id, size);
+        id
+    }
+
+    fn collector(tree: &Tree, count: usize) -> Collector<'_> {
+        Collector {
+            tree,
+            diff: None,
+            counts: Vec::new(),
+            categories: Vec::new(),
+            modified: Vec::new(),
+            accessed: Vec::new(),
+            mode: SizeMode::Apparent,
+            count,
+            max_depth: 8,
+        }
+    }
+
+    fn collect_root(tree: &Tree, count: usize) -> Arc<Entry> {
+        let size = tree.size(Tree::ROOT).apparent;
+        collector(tree, count).entry(Tree::ROOT, size, 0)
+    }
+
+    #[test]
+    fn shown_entries_and_others_add_up_to_the_parent() {
+        let mut tree = Tree::new(Path::new("/data"));
+        let sub = tree.add(Tree::ROOT, OsStr::new("sub"), Kind::Dir, Size::default(), 0);
+        for (name, size) in [("x", 30), ("y", 20), ("z", 15)] {
+            file(&mut tree, sub, name, size);
+        }
+        for (name, size) in [("a", 50), ("b", 40), ("c", 30), ("d", 20), ("e", 10)] {
+            file(&mut tree, Tree::ROOT, name, size);
+        }
+
+        let root = collect_root(&tree, 2);
+        let names: Vec<_> = root.children.iter().map(|v| v.path.clone()).collect();
+        assert_eq!(names[..2], [Path::new("/data/sub"), Path::new("/data/a")]);
+        let others = root.children.last().unwrap();
+        assert!(is_others(others));
+        assert_eq!(others.node, Tree::ROOT);
+        assert_eq!(others.others.len(), 4);
+        assert_eq!(others.size, 40 + 30 + 20 + 10);
+
+        fn check(entry: &Entry) {
+            if entry.children.is_empty() {
+                return;
+            }
+            let sum: u64 = entry.children.iter().map(|v| v.size).sum();
+            assert_eq!(sum, entry.size, "{}", entry.path.display());
+            for v in entry.children.iter() {
+                assert_eq!(v.parent_size, entry.size);
+                check(v);
+            }
+        }
+        check(&root);
+        assert_eq!(root.size, 65 + 150);
+    }
+
+    #[test]
+    fn keeps_mount_points_past_max_count() {
+        let mut tree = Tree::new(Path::new("/data"));
+        for (name, size) in [("a", 50), ("b", 40), ("c", 30)] {
+            file(&mut tree, Tree::ROOT, name, size);
+        }
+        let mount = tree.add(
+            Tree::ROOT,
+            OsStr::new("mnt"),
+            Kind::MountPoint,
+            Size::default(),
+            0,
+        );
+
+        let root = collect_root(&tree, 1);
+        assert_eq!(root.children.len(), 3);
+        let shown = root.children.iter().find(|v| v.node == mount).unwrap();
+        assert!(is_unscanned_mount(shown));
+        let others = root.children.iter().find(|v| is_others(v)).unwrap();
+        assert_eq!(others.others.len(), 2);
+        assert!(!others.others.contains(&mount));
+    }
+
+    #[test]
+    fn flattens_nested_others() {
+        let mut tree = Tree::new(Path::new("/data"));
+        let a = file(&mut tree, Tree::ROOT, "a", 50);
+        let b = file(&mut tree, Tree::ROOT, "b", 40);
+        let c = file(&mut tree, Tree::ROOT, "c", 30);
+
+        let root = collect_root(&tree, 1);
+        assert_eq!(root.children.len(), 2);
+        let folded = fold_others(&root, root.children.iter());
+        assert!(is_others(&folded));
+        assert_eq!(folded.node, Tree::ROOT);
+        let mut nodes = folded.others.to_vec();
+        nodes.sort_unstable();
+        assert_eq!(nodes, vec![a, b, c]);
+        assert_eq!(folded.size, root.size);
+        assert_eq!(folded.sizes, tree.size(Tree::ROOT));
+        assert_eq!(folded.parent_size, root.size);
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn squarifies_rows() {
+        let mut tree = Tree::new(Path::new("/data"));
+        file(&mut tree, Tree::ROOT, "a", 1);
+        let entry = collect_root(&tree, 1);
+        let a = &entry.children[0];
+
+        // A square, then two tiles of 5 by 2.5 laid along the side of 5.
+        assert!(close(worst_ratio(&[(a, 25.0)], 5.0), 1.0));
+        assert!(close(worst_ratio(&[(a, 12.5), (a, 12.5)], 5.0), 2.0));
+
+        let mut tiles = Vec::new();
+        let rect = Rect::new(0.0, 0.0, 10.0, 6.0);
+        let rest = place_row(&[(a, 18.0), (a, 12.0)], rect, 1.0, &mut tiles);
+        assert_eq!(rest, Rect::new(5.0, 0.0, 10.0, 6.0));
+        assert_eq!(tiles.len(), 2);
+        assert!(close(tiles[0].rect.area(), 18.0));
+        assert!(close(tiles[1].rect.area(), 12.0));
+        assert!(close(tiles[0].rect.width(), 5.0));
+        assert!(close(tiles[1].rect.y1, 6.0));
+    }
+
+    #[test]
+    fn tiles_cover_the_share_of_their_entry() {
+        let mut tree = Tree::new(Path::new("/data"));
+        for (name, size) in [("a", 50), ("b", 30), ("c", 20)] {
+            file(&mut tree, Tree::ROOT, name, size);
+        }
+        let entry = collect_root(&tree, 10);
+
+        let rect = Rect::new(0.0, 0.0, 100.0, 50.0);
+        let mut tiles = Vec::new();
+        create_tiles(&entry, rect, 1.0, &mut tiles);
+        assert_eq!(tiles.len(), 3);
+        for tile in &tiles {
+            let share = tile.entry.size as f64 / entry.size as f64;
+            assert!(close(tile.rect.area(), share * rect.area()));
+            assert_eq!(tile.rect.intersect(rect), tile.rect);
+        }
+        let area: f64 = tiles.iter().map(|tile| tile.rect.area()).sum();
+        assert!(close(area, rect.area()));
+    }
+
+    #[test]
+    fn folds_small_tiles_into_others() {
+        let mut tree = Tree::new(Path::new("/data"));
+        for (name, size) in [("a", 90), ("b", 5), ("c", 3), ("d", 2)] {
+            file(&mut tree, Tree::ROOT, name, size);
+        }
+        let entry = collect_root(&tree, 10);
+
+        let rect = Rect::new(0.0, 0.0, 10.0, 10.0);
+        let mut tiles = Vec::new();
+        create_tiles(&entry, rect, 6.0, &mut tiles);
+        assert_eq!(tiles.len(), 2);
+        assert_eq!(tiles[0].entry.path, Path::new("/data/a"));
+        let others = &tiles[1];
+        assert!(is_others(&others.entry));
+        assert_eq!(others.entry.others.len(), 3);
+        assert!(close(others.rect.area(), 10.0));
+
+        // Too small even together, they are left out.
+        tiles.clear();
+        create_tiles(&entry, rect, 11.0, &mut tiles);
+        assert_eq!(tiles.len(), 1);
+    }
+
+    #[test]
+    fn excludes_exactly_one_path() {
+        use ignore::gitignore::GitignoreBuilder;
+
+        let root = Path::new("/data");
+        let mut names = vec![
+            ("a*b", vec!["axb", "a*bc"]),
+            ("q?", vec!["qx", "q"]),
+            ("[x]", vec!["x", "[y]"]),
+            ("trail ", vec!["trail", "trail  "]),
+            ("two  ", vec!["two", "two "]),
+            ("dir*/[x] ", vec!["dirs/[x] ", "dir*/x"]),
+        ];
+        if cfg!(unix) {
+            names.push(("back\\slash", vec!["back/slash", "backslash"]));
+        }
+        for (name, others) in names {
+            let mut builder = GitignoreBuilder::new(root);
+            let pattern = exclude_pattern(Path::new(name));
+            builder.add_line(None, &pattern).unwrap();
+            let excludes = builder.build().unwrap();
+            let matched = |name: &str| excludes.matched(root.join(name), false).is_ignore();
+            assert!(matched(name), "{:?} does not match {:?}", pattern, name);
+            for other in others {
+                assert!(!matched(other), "{:?} matches {:?}", pattern, other);
+            }
+        }
+    }
+}
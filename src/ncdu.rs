@@ -0,0 +1,593 @@
+//! The JSON export format of ncdu, see https://dev.yorhel.nl/ncdu/jsonfmt.
+//!
+//! A directory is an array of its own info block followed by its entries,
+//! files are info blocks. Sizes of directories are those of the directory
+//! itself, not of its content.
+
+use crate::{
+    dir::{ScanError, ScanErrorKind, ScanOptions},
+    snapshot::Info,
+    tree::{Kind, NodeId, Size, Tree},
+};
+use serde::{
+    de::{self, value::MapAccessDeserializer, DeserializeSeed, MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use serde_json::Value;
+use std::{
+    collections::HashSet,
+    ffi::{OsStr, OsString},
+    fmt,
+    fs::File,
+    io::{self, BufReader, BufWriter, Error, Read, Write},
+    path::{Path, PathBuf},
+};
+
+pub const EXTENSION: &str = "json";
+
+const MAJOR_VERSION: u64 = 1;
+const MINOR_VERSION: u64 = 1;
+
+// Directories are read recursively, deeper dumps would run out of the stack
+// of a thread.
+const MAX_DEPTH: usize = 512;
+
+/// Whether the file at `path` looks like an ncdu dump rather than a snapshot.
+pub fn is_dump(path: &Path) -> bool {
+    let mut start = [0u8; 16];
+    match File::open(path).and_then(|mut file| file.read(&mut start)) {
+        Ok(len) => start[..len].iter().find(|byte| !byte.is_ascii_whitespace()) == Some(&b'['),
+        Err(_) => false,
+    }
+}
+
+// ncdu writes names as they are on disk, even when they are not valid UTF-8.
+struct Name(Vec<u8>);
+
+impl Name {
+    #[cfg(unix)]
+    fn into_os_string(self) -> OsString {
+        use std::os::unix::ffi::OsStringExt;
+        OsString::from_vec(self.0)
+    }
+
+    #[cfg(not(unix))]
+    fn into_os_string(self) -> OsString {
+        OsString::from(String::from_utf8_lossy(&self.0).into_owned())
+    }
+}
+
+impl<'de> Deserialize<'de> for Name {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct NameVisitor;
+
+        impl<'de> Visitor<'de> for NameVisitor {
+            type Value = Name;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a name")
+            }
+
+            fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Name, E> {
+                Ok(Name(bytes.to_vec()))
+            }
+
+            fn visit_str<E: de::Error>(self, text: &str) -> Result<Name, E> {
+                Ok(Name(text.as_bytes().to_vec()))
+            }
+        }
+
+        // serde_json hands out the raw bytes of strings as bytes.
+        deserializer.deserialize_bytes(NameVisitor)
+    }
+}
+
+#[cfg(unix)]
+fn name_bytes(name: &OsStr) -> std::borrow::Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    std::borrow::Cow::Borrowed(name.as_bytes())
+}
+
+#[cfg(not(unix))]
+fn name_bytes(name: &OsStr) -> std::borrow::Cow<'_, [u8]> {
+    std::borrow::Cow::Owned(name.to_string_lossy().into_owned().into_bytes())
+}
+
+/// The info block of an entry, with the fields we have a use for.
+#[derive(Deserialize)]
+struct Item {
+    name: Name,
+    #[serde(default)]
+    asize: u64,
+    #[serde(default)]
+    dsize: u64,
+    dev: Option<u64>,
+    ino: Option<u64>,
+    #[serde(default)]
+    hlnkc: bool,
+    #[serde(default)]
+    read_error: bool,
+    excluded: Option<Value>,
+    #[serde(default)]
+    notreg: bool,
+    #[serde(default)]
+    mtime: u64,
+}
+
+#[derive(Default, Deserialize)]
+struct Metadata {
+    timestamp: Option<u64>,
+}
+
+struct Loader {
+    tree: Tree,
+    errors: Vec<ScanError>,
+    deduplicated: Size,
+    inodes: HashSet<(u64, u64)>,
+}
+
+impl Loader {
+    // Adds the entry described by `item` below `parent`, the root when there is
+    // none. Entries ncdu left out other than mount points are skipped.
+    fn add(
+        &mut self,
+        parent: Option<NodeId>,
+        item: Item,
+        dev: u64,
+        is_dir: bool,
+    ) -> Option<NodeId> {
+        let kind = match &item.excluded {
+            Some(Value::String(reason)) if reason == "otherfs" => Kind::MountPoint,
+            Some(_) => return None,
+            None if is_dir && parent.is_some() && item.dev.is_some_and(|id| id != dev) => {
+                Kind::MountPoint
+            }
+            None if is_dir => Kind::Dir,
+            // ncdu only tells that an entry is not a regular file, most of
+            // those are symlinks.
+            None if item.notreg => Kind::Symlink,
+            None => Kind::File,
+        };
+        let name = item.name.into_os_string();
+        let mut size = Size {
+            apparent: item.asize,
+            allocated: item.dsize,
+        };
+        if item.hlnkc {
+            if let Some(ino) = item.ino {
+                if !self.inodes.insert((item.dev.unwrap_or(dev), ino)) {
+                    self.deduplicated += size;
+                    size = Size::default();
+                }
+            }
+        }
+        let mtime = item.mtime.min(u32::MAX as u64) as u32;
+
+        let id = match parent {
+            Some(parent) => {
+                if item.read_error && !is_dir {
+                    // The scanner leaves out what it cannot stat as well.
+                    let path = self.tree.path(parent).join(&name);
+                    self.errors.push(read_error(path));
+                    return None;
+                }
+                self.tree.add(parent, &name, kind, Size::default(), mtime)
+            }
+            None => {
+                self.tree = Tree::new(Path::new(&name));
+                self.tree.set_mtime(Tree::ROOT, mtime);
+                Tree::ROOT
+            }
+        };
+        self.tree.grow(id, size);
+        if item.read_error {
+            let path = self.tree.path(id);
+            self.errors.push(read_error(path));
+        }
+        Some(id)
+    }
+}
+
+fn read_error(path: PathBuf) -> ScanError {
+    ScanError {
+        path,
+        kind: ScanErrorKind::Io,
+        message: String::from("ncdu could not read it"),
+    }
+}
+
+// A directory, the array of its info block and its entries.
+struct DirSeed<'a> {
+    loader: &'a mut Loader,
+    parent: Option<NodeId>,
+    dev: u64,
+    depth: usize,
+}
+
+impl<'de> DeserializeSeed<'de> for DirSeed<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for DirSeed<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a directory")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        let item: Item = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::custom("directory without an info block"))?;
+        let dev = item.dev.unwrap_or(self.dev);
+        let id = self.loader.add(self.parent, item, self.dev, true);
+        while seq
+            .next_element_seed(EntrySeed {
+                loader: self.loader,
+                parent: id,
+                dev,
+                depth: self.depth + 1,
+            })?
+            .is_some()
+        {}
+        Ok(())
+    }
+}
+
+// A file or a directory, `parent` is `None` below a skipped directory.
+struct EntrySeed<'a> {
+    loader: &'a mut Loader,
+    parent: Option<NodeId>,
+    dev: u64,
+    depth: usize,
+}
+
+impl<'de> DeserializeSeed<'de> for EntrySeed<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for EntrySeed<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a file or a directory")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        if self.depth > MAX_DEPTH {
+            return Err(de::Error::custom(format!(
+                "directories nested more than {} levels deep",
+                MAX_DEPTH
+            )));
+        }
+        match self.parent {
+            Some(parent) => DirSeed {
+                loader: self.loader,
+                parent: Some(parent),
+                dev: self.dev,
+                depth: self.depth,
+            }
+            .visit_seq(seq),
+            None => {
+                // Skipped, the depth still counts.
+                while seq
+                    .next_element_seed(EntrySeed {
+                        loader: self.loader,
+                        parent: None,
+                        dev: self.dev,
+                        depth: self.depth + 1,
+                    })?
+                    .is_some()
+                {}
+                Ok(())
+            }
+        }
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<(), A::Error> {
+        let item = Item::deserialize(MapAccessDeserializer::new(map))?;
+        if let Some(parent) = self.parent {
+            self.loader.add(Some(parent), item, self.dev, false);
+        }
+        Ok(())
+    }
+}
+
+// [major, minor, metadata, root]
+struct DumpVisitor<'a> {
+    loader: &'a mut Loader,
+}
+
+impl<'de> Visitor<'de> for DumpVisitor<'_> {
+    type Value = u64;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "an ncdu dump")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<u64, A::Error> {
+        let major: u64 = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::custom("missing version"))?;
+        if major != MAJOR_VERSION {
+            return Err(de::Error::custom(format!(
+                "version {} is not supported",
+                major
+            )));
+        }
+        let _minor: Option<u64> = seq.next_element()?;
+        let metadata: Metadata = seq.next_element()?.unwrap_or_default();
+        seq.next_element_seed(DirSeed {
+            loader: self.loader,
+            parent: None,
+            dev: 0,
+            depth: 0,
+        })?
+        .ok_or_else(|| de::Error::custom("missing root directory"))?;
+        while seq.next_element::<de::IgnoredAny>()?.is_some() {}
+        Ok(metadata.timestamp.unwrap_or(0))
+    }
+}
+
+fn read(reader: impl Read) -> Result<(Tree, Info), Error> {
+    let mut loader = Loader {
+        tree: Tree::new(Path::new("")),
+        errors: Vec::new(),
+        deduplicated: Size::default(),
+        inodes: HashSet::new(),
+    };
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    // Each directory is an array in the one of its parent, a real tree can
+    // go deeper than the default limit of serde_json. `MAX_DEPTH` applies.
+    deserializer.disable_recursion_limit();
+    let scanned_at = deserializer
+        .deserialize_seq(DumpVisitor {
+            loader: &mut loader,
+        })
+        .and_then(|scanned_at| deserializer.end().map(|_| scanned_at))
+        .map_err(|err| {
+            Error::new(
+                io::ErrorKind::InvalidData,
+                format!("not an ncdu dump ({})", err),
+            )
+        })?;
+    let info = Info {
+        scanned_at,
+        options: ScanOptions::default(),
+        errors: loader.errors,
+        deduplicated: loader.deduplicated,
+    };
+    Ok((loader.tree, info))
+}
+
+/// Reads a dump written by `ncdu -o`.
+pub fn load(path: &Path) -> Result<(Tree, Info), Error> {
+    read(BufReader::new(File::open(path)?))
+}
+
+fn write_name(writer: &mut impl Write, name: &OsStr) -> Result<(), Error> {
+    writer.write_all(b"\"")?;
+    for &byte in name_bytes(name).iter() {
+        match byte {
+            b'"' => writer.write_all(b"\\\"")?,
+            b'\\' => writer.write_all(b"\\\\")?,
+            0..=0x1f => write!(writer, "\\u{:04x}", byte)?,
+            _ => writer.write_all(&[byte])?,
+        }
+    }
+    writer.write_all(b"\"")
+}
+
+fn write_item(
+    writer: &mut impl Write,
+    tree: &Tree,
+    id: NodeId,
+    size: Size,
+    extra: &str,
+) -> Result<(), Error> {
+    writer.write_all(b"{\"name\":")?;
+    write_name(writer, tree.name(id))?;
+    write!(
+        writer,
+        ",\"asize\":{},\"dsize\":{}",
+        size.apparent, size.allocated
+    )?;
+    if tree.mtime(id) != 0 {
+        write!(writer, ",\"mtime\":{}", tree.mtime(id))?;
+    }
+    write!(writer, "{}}}", extra)
+}
+
+// Mount points get a device number of their own, made up as the real one is
+// not known, so that ncdu sees the file systems change.
+fn write_entry(
+    writer: &mut impl Write,
+    tree: &Tree,
+    id: NodeId,
+    errors: &HashSet<&Path>,
+    devices: &mut u64,
+) -> Result<(), Error> {
+    let has_children = tree.children(id).next().is_some();
+    match tree.kind(id) {
+        Kind::File => return write_item(writer, tree, id, tree.size(id), ""),
+        Kind::Symlink if !has_children => {
+            return write_item(writer, tree, id, tree.size(id), ",\"notreg\":true")
+        }
+        Kind::MountPoint if !has_children => {
+            return write_item(writer, tree, id, tree.size(id), ",\"excluded\":\"otherfs\"")
+        }
+        _ => {}
+    }
+
+    // What is left of the size once the entries are accounted for belongs to
+    // the directory itself.
+    let mut size = tree.size(id);
+    for child in tree.children(id) {
+        let child = tree.size(child);
+        size.apparent = size.apparent.saturating_sub(child.apparent);
+        size.allocated = size.allocated.saturating_sub(child.allocated);
+    }
+    let mut extra = String::new();
+    if tree.kind(id) == Kind::MountPoint {
+        *devices += 1;
+        extra += &format!(",\"dev\":{}", devices);
+    }
+    if !errors.is_empty() && errors.contains(tree.path(id).as_path()) {
+        extra += ",\"read_error\":true";
+    }
+    writer.write_all(b"[")?;
+    write_item(writer, tree, id, size, &extra)?;
+    for child in tree.children(id) {
+        // The total of the excluded paths is not a path ncdu could show.
+        if tree.kind(child) != Kind::Excluded {
+            writer.write_all(b",\n")?;
+            write_entry(writer, tree, child, errors, devices)?;
+        }
+    }
+    writer.write_all(b"]")
+}
+
+fn write(writer: &mut impl Write, tree: &Tree, info: &Info) -> Result<(), Error> {
+    let errors: HashSet<&Path> = info
+        .errors
+        .iter()
+        .map(|error| error.path.as_path())
+        .collect();
+    writeln!(
+        writer,
+        "[{},{},{{\"progname\":\"rustitude\",\"progver\":\"{}\",\"timestamp\":{}}},",
+        MAJOR_VERSION,
+        MINOR_VERSION,
+        env!("CARGO_PKG_VERSION"),
+        info.scanned_at
+    )?;
+    write_entry(writer, tree, Tree::ROOT, &errors, &mut 0)?;
+    writer.write_all(b"]\n")
+}
+
+/// Writes the tree in the format of `ncdu -o`, which `ncdu -f` can read.
+pub fn save(path: &Path, tree: &Tree, info: &Info) -> Result<(), Error> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write(&mut writer, tree, info)?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Trimmed from `ncdu -o` output, with a hard link, another file system,
+    // an excluded path and a directory that could not be read.
+    const SAMPLE: &str = r#"[1,2,{"progname":"ncdu","progver":"1.18","timestamp":1650000000},
+[{"name":"/srv","asize":4096,"dsize":4096,"dev":2049,"ino":2},
+[{"name":"www","asize":4096,"dsize":4096,"ino":11,"mtime":1649000000},
+{"name":"index.html","asize":1500,"dsize":4096,"ino":12,"mtime":1649000001},
+{"name":"logo.png","asize":30000,"dsize":32768,"ino":13,"hlnkc":true}],
+{"name":"logo-copy.png","asize":30000,"dsize":32768,"ino":13,"hlnkc":true},
+{"name":"cache","excluded":"pattern"},
+{"name":"nfs","asize":0,"dsize":0,"excluded":"otherfs"},
+{"name":"current","asize":3,"dsize":0,"ino":14,"notreg":true},
+[{"name":"private","asize":4096,"dsize":4096,"ino":15,"read_error":true}]]]
+"#;
+
+    fn find(tree: &Tree, name: &str) -> NodeId {
+        (0..tree.len() as NodeId)
+            .find(|id| tree.name(*id) == OsStr::new(name))
+            .unwrap()
+    }
+
+    #[test]
+    fn reads_sample_dump() {
+        let (tree, info) = read(SAMPLE.as_bytes()).unwrap();
+
+        assert_eq!(tree.path(Tree::ROOT), Path::new("/srv"));
+        assert_eq!(info.scanned_at, 1650000000);
+        assert_eq!(tree.len(), 8);
+        assert_eq!(tree.kind(find(&tree, "www")), Kind::Dir);
+        assert_eq!(tree.kind(find(&tree, "nfs")), Kind::MountPoint);
+        assert_eq!(tree.kind(find(&tree, "current")), Kind::Symlink);
+        assert_eq!(tree.mtime(find(&tree, "www")), 1649000000);
+        assert!(!(0..tree.len() as NodeId).any(|id| tree.name(id) == OsStr::new("cache")));
+        // Directories count for themselves and the hard link only once.
+        assert_eq!(tree.size(find(&tree, "www")).apparent, 4096 + 1500 + 30000);
+        assert_eq!(
+            tree.size(Tree::ROOT).apparent,
+            4096 + (4096 + 1500 + 30000) + 3 + 4096
+        );
+        assert_eq!(info.deduplicated.apparent, 30000);
+        assert_eq!(info.errors.len(), 1);
+        assert_eq!(info.errors[0].path, Path::new("/srv/private"));
+    }
+
+    #[test]
+    fn round_trips() {
+        let (tree, info) = read(SAMPLE.as_bytes()).unwrap();
+        let mut written = Vec::new();
+        write(&mut written, &tree, &info).unwrap();
+        let (loaded, loaded_info) = read(written.as_slice()).unwrap();
+
+        assert_eq!(loaded_info.scanned_at, info.scanned_at);
+        assert_eq!(loaded_info.errors.len(), 1);
+        assert_eq!(loaded.len(), tree.len());
+        for id in 0..tree.len() as NodeId {
+            let other = find(&loaded, &tree.name(id).to_string_lossy());
+            assert_eq!(loaded.path(other), tree.path(id));
+            assert_eq!(loaded.kind(other), tree.kind(id));
+            assert_eq!(loaded.size(other), tree.size(id));
+            assert_eq!(loaded.mtime(other), tree.mtime(id));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_names_that_are_not_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let name = OsStr::from_bytes(b"caf\xe9 \"1\"\n");
+        let mut tree = Tree::new(Path::new("/data"));
+        tree.add(Tree::ROOT, name, Kind::File, Size::default(), 0);
+        let info = Info {
+            scanned_at: 0,
+            options: ScanOptions::default(),
+            errors: Vec::new(),
+            deduplicated: Size::default(),
+        };
+        let mut written = Vec::new();
+        write(&mut written, &tree, &info).unwrap();
+
+        let (loaded, _) = read(written.as_slice()).unwrap();
+        let child = loaded.children(Tree::ROOT).next().unwrap();
+        assert_eq!(loaded.name(child), name);
+    }
+
+    // A directory per level below /data, each holding the next one.
+    fn nested(levels: usize) -> String {
+        let mut dump = String::from(r#"[1,2,{"timestamp":0},[{"name":"/data"}"#);
+        for _ in 0..levels {
+            dump += r#",[{"name":"d"}"#;
+        }
+        dump += &"]".repeat(levels + 2);
+        dump
+    }
+
+    #[test]
+    fn reads_deeply_nested_directories() {
+        let (tree, _) = read(nested(MAX_DEPTH).as_bytes()).unwrap();
+        assert_eq!(tree.len(), MAX_DEPTH + 1);
+        let path = tree.path(MAX_DEPTH as NodeId);
+        assert_eq!(path.components().count(), MAX_DEPTH + 2);
+
+        match read(nested(MAX_DEPTH + 1).as_bytes()) {
+            Err(err) => assert!(err.to_string().contains("nested more than")),
+            Ok(_) => panic!("read a dump nested too deep"),
+        }
+    }
+}
@@ -0,0 +1,310 @@
+use crate::{
+    dir::{ScanError, ScanOptions},
+    ncdu,
+    tree::{Kind, NodeId, Size, Tree},
+};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize, Serializer};
+use std::{
+    ffi::{OsStr, OsString},
+    fs::File,
+    io::{self, BufReader, BufWriter, Error, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// Snapshots are gzipped JSON, bump VERSION whenever the layout changes.
+const FORMAT: &str = "rustitude-snapshot";
+const VERSION: u32 = 2;
+
+pub const EXTENSION: &str = "rustitude";
+
+/// Everything about a scan besides the tree.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Info {
+    /// Seconds since the epoch when the scan started.
+    pub scanned_at: u64,
+    pub options: ScanOptions,
+    pub errors: Vec<ScanError>,
+    pub deduplicated: Size,
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+/// (year, month, day) of a time in seconds since the epoch, in UTC. See
+/// http://howardhinnant.github.io/date_algorithms.html
+pub fn date(seconds: u64) -> (i64, i64, i64) {
+    let days = (seconds / (24 * 60 * 60)) as i64 + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+// Names are written as text when they are valid UTF-8, as bytes otherwise.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Name {
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+impl Name {
+    #[cfg(unix)]
+    fn new(name: &OsStr) -> Self {
+        use std::os::unix::ffi::OsStrExt;
+        match name.to_str() {
+            Some(text) => Name::Text(text.to_owned()),
+            None => Name::Bytes(name.as_bytes().to_vec()),
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn new(name: &OsStr) -> Self {
+        Name::Text(name.to_string_lossy().into_owned())
+    }
+
+    fn into_os_string(self) -> OsString {
+        match self {
+            Name::Text(text) => OsString::from(text),
+            #[cfg(unix)]
+            Name::Bytes(bytes) => {
+                use std::os::unix::ffi::OsStringExt;
+                OsString::from_vec(bytes)
+            }
+            #[cfg(not(unix))]
+            Name::Bytes(bytes) => OsString::from(String::from_utf8_lossy(&bytes).into_owned()),
+        }
+    }
+}
+
+// (parent, name, kind, apparent, allocated, mtime, atime), in the order of the
+// ids so that parents come before their children. The parent of the root is
+// unused. Version 1 had no atime.
+#[derive(Serialize, Deserialize)]
+struct Record(NodeId, Name, Kind, u64, u64, u32, #[serde(default)] u32);
+
+struct Nodes<'a>(&'a Tree);
+
+impl Serialize for Nodes<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let tree = self.0;
+        // Detached nodes are left out and the others numbered again, parents
+        // still come first.
+        let mut ids: Vec<Option<NodeId>> = vec![None; tree.len()];
+        let mut next = 0;
+        for id in 0..tree.len() as NodeId {
+            let kept = match tree.parent(id) {
+                Some(parent) => ids[parent as usize].is_some(),
+                None => id == Tree::ROOT,
+            };
+            if kept {
+                ids[id as usize] = Some(next);
+                next += 1;
+            }
+        }
+        serializer.collect_seq(
+            (0..tree.len() as NodeId)
+                .filter(|id| ids[*id as usize].is_some())
+                .map(|id| {
+                    let size = tree.size(id);
+                    Record(
+                        tree.parent(id)
+                            .and_then(|parent| ids[parent as usize])
+                            .unwrap_or(Tree::ROOT),
+                        Name::new(tree.name(id)),
+                        tree.kind(id),
+                        size.apparent,
+                        size.allocated,
+                        tree.mtime(id),
+                        tree.atime(id),
+                    )
+                }),
+        )
+    }
+}
+
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    format: &'a str,
+    version: u32,
+    info: &'a Info,
+    nodes: Nodes<'a>,
+}
+
+#[derive(Deserialize)]
+struct Snapshot {
+    format: String,
+    version: u32,
+    info: Info,
+    nodes: Vec<Record>,
+}
+
+pub fn save(path: &Path, tree: &Tree, info: &Info) -> Result<(), Error> {
+    let mut writer = BufWriter::new(GzEncoder::new(File::create(path)?, Compression::default()));
+    let snapshot = SnapshotRef {
+        format: FORMAT,
+        version: VERSION,
+        info,
+        nodes: Nodes(tree),
+    };
+    serde_json::to_writer(&mut writer, &snapshot)?;
+    writer.flush()?;
+    writer.into_inner()?.finish()?;
+    Ok(())
+}
+
+/// Reads a snapshot written by `save`, without touching the scanned files.
+pub fn load(path: &Path) -> Result<(Tree, Info), Error> {
+    let invalid = |message: String| Error::new(io::ErrorKind::InvalidData, message);
+
+    let reader = BufReader::new(GzDecoder::new(File::open(path)?));
+    let snapshot: Snapshot = serde_json::from_reader(reader)
+        .map_err(|err| invalid(format!("not a snapshot ({})", err)))?;
+    if snapshot.format != FORMAT {
+        return Err(invalid(String::from("not a snapshot")));
+    }
+    if snapshot.version == 0 || snapshot.version > VERSION {
+        return Err(invalid(format!(
+            "snapshot version {} is not supported",
+            snapshot.version
+        )));
+    }
+
+    let mut records = snapshot.nodes.into_iter();
+    let root = records
+        .next()
+        .ok_or_else(|| invalid(String::from("empty snapshot")))?;
+    let mut tree = Tree::new(Path::new(&root.1.into_os_string()));
+    tree.grow(
+        Tree::ROOT,
+        Size {
+            apparent: root.3,
+            allocated: root.4,
+        },
+    );
+    tree.set_mtime(Tree::ROOT, root.5);
+    tree.set_atime(Tree::ROOT, root.6);
+    for (id, record) in (1..).zip(records) {
+        if record.0 >= id {
+            return Err(invalid(format!("node {} comes before its parent", id)));
+        }
+        let size = Size {
+            apparent: record.3,
+            allocated: record.4,
+        };
+        let id = tree.add(
+            record.0,
+            &record.1.into_os_string(),
+            record.2,
+            size,
+            record.5,
+        );
+        tree.set_atime(id, record.6);
+    }
+    Ok((tree, snapshot.info))
+}
+
+/// Reads a snapshot, or a dump of ncdu.
+pub fn open(path: &Path) -> Result<(Tree, Info), Error> {
+    if ncdu::is_dump(path) {
+        ncdu::load(path)
+    } else {
+        load(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let root = std::env::temp_dir().join("rustitude-snapshot-root");
+        let mut tree = Tree::new(&root);
+        let size = Size {
+            apparent: 5,
+            allocated: 4096,
+        };
+        let dir = tree.add(Tree::ROOT, OsStr::new("dir"), Kind::Dir, Size::default(), 1);
+        let file = tree.add(dir, OsStr::new("file"), Kind::File, size, 2);
+        tree.set_atime(file, 3);
+        tree.grow(dir, size);
+        let info = Info {
+            scanned_at: now(),
+            options: ScanOptions::default(),
+            errors: Vec::new(),
+            deduplicated: Size::default(),
+        };
+
+        let path = std::env::temp_dir().join(format!(
+            "rustitude-snapshot-{}.{}",
+            std::process::id(),
+            EXTENSION
+        ));
+        save(&path, &tree, &info).unwrap();
+        let loaded = load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let (loaded, loaded_info) = loaded.unwrap();
+        assert_eq!(loaded_info.scanned_at, info.scanned_at);
+        assert_eq!(loaded.len(), tree.len());
+        assert_eq!(loaded.path(file), root.join("dir").join("file"));
+        assert_eq!(loaded.size(Tree::ROOT), size);
+        assert_eq!(loaded.size(file), size);
+        assert_eq!(loaded.kind(dir), Kind::Dir);
+        assert_eq!(loaded.mtime(file), 2);
+        assert_eq!(loaded.atime(file), 3);
+    }
+
+    #[test]
+    fn leaves_out_detached_nodes() {
+        let mut tree = Tree::new(Path::new("/data"));
+        let size = Size {
+            apparent: 5,
+            allocated: 8,
+        };
+        let dir = tree.add(Tree::ROOT, OsStr::new("dir"), Kind::Dir, Size::default(), 0);
+        let old = tree.add(dir, OsStr::new("old"), Kind::Dir, Size::default(), 0);
+        tree.add(old, OsStr::new("file"), Kind::File, size, 0);
+        tree.grow(old, size);
+        let kept = tree.add(dir, OsStr::new("kept"), Kind::File, size, 0);
+        tree.grow(dir, size);
+
+        tree.detach(old);
+
+        let path = std::env::temp_dir().join(format!(
+            "rustitude-detached-{}.{}",
+            std::process::id(),
+            EXTENSION
+        ));
+        let info = Info {
+            scanned_at: 0,
+            options: ScanOptions::default(),
+            errors: Vec::new(),
+            deduplicated: Size::default(),
+        };
+        save(&path, &tree, &info).unwrap();
+        let loaded = load(&path);
+        std::fs::remove_file(&path).unwrap();
+        let (loaded, _) = loaded.unwrap();
+        assert_eq!(loaded.len(), 3);
+        assert_eq!(loaded.path(2), Path::new("/data/dir/kept"));
+        assert_eq!(loaded.size(2), tree.size(kept));
+        assert_eq!(loaded.size(Tree::ROOT), size);
+    }
+}
@@ -0,0 +1,207 @@
+use std::{
+    io::{self, Error},
+    path::{Path, PathBuf},
+};
+
+/// A file or directory moved to the trash, enough to put it back.
+#[derive(Clone, Debug)]
+pub struct Trashed {
+    pub original: PathBuf,
+    file: PathBuf,
+    info: PathBuf,
+}
+
+/// Moves `path` to the trash of the freedesktop.org specification, the home
+/// trash or the one at the top of the file system holding `path`.
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn trash(path: &Path) -> Result<Trashed, Error> {
+    trash_with(path, &data_home()?)
+}
+
+// `data` is the data directory of the user, where the home trash is.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn trash_with(path: &Path, data: &Path) -> Result<Trashed, Error> {
+    use std::{
+        fs::{self, DirBuilder, OpenOptions},
+        io::Write,
+        os::unix::fs::{DirBuilderExt, MetadataExt},
+    };
+
+    // Paths of the tree are absolute already.
+    let original = path.to_path_buf();
+    let name = original
+        .file_name()
+        .ok_or_else(|| Error::new(io::ErrorKind::InvalidInput, "cannot trash a root"))?;
+    let device = fs::symlink_metadata(&original)?.dev();
+    let trash = trash_dir(&original, device, data)?;
+    let files = trash.join("files");
+    let infos = trash.join("info");
+    // Only for the user, as the specification requires of `.Trash-$uid`.
+    let mut builder = DirBuilder::new();
+    builder.recursive(true).mode(0o700);
+    builder.create(&files)?;
+    builder.create(&infos)?;
+
+    // The info file is created first and exclusively, it reserves the name.
+    let info_text = format!(
+        "[Trash Info]\nPath={}\nDeletionDate={}\n",
+        encode_path(&original),
+        deletion_date(crate::snapshot::now())
+    );
+    for attempt in 1.. {
+        let mut file_name = name.to_os_string();
+        if attempt > 1 {
+            file_name.push(format!(".{}", attempt));
+        }
+        let mut info_name = file_name.clone();
+        info_name.push(".trashinfo");
+        let info = infos.join(info_name);
+        let mut info_file = match OpenOptions::new().write(true).create_new(true).open(&info) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        };
+        let file = files.join(file_name);
+        if file.symlink_metadata().is_ok() {
+            // Left over without its info file, keep it.
+            fs::remove_file(&info)?;
+            continue;
+        }
+        let result = info_file
+            .write_all(info_text.as_bytes())
+            .and_then(|_| fs::rename(&original, &file));
+        if let Err(err) = result {
+            let _ = fs::remove_file(&info);
+            return Err(err);
+        }
+        return Ok(Trashed {
+            original,
+            file,
+            info,
+        });
+    }
+    unreachable!()
+}
+
+// macOS has a trash of its own, with no documented layout.
+#[cfg(any(not(unix), target_os = "macos"))]
+pub fn trash(_path: &Path) -> Result<Trashed, Error> {
+    Err(Error::new(
+        io::ErrorKind::Unsupported,
+        "moving to the trash is not supported on Windows and macOS yet",
+    ))
+}
+
+/// Moves an entry out of the trash, back where it was.
+pub fn restore(trashed: &Trashed) -> Result<(), Error> {
+    if trashed.original.symlink_metadata().is_ok() {
+        return Err(Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists again", trashed.original.display()),
+        ));
+    }
+    std::fs::rename(&trashed.file, &trashed.original)?;
+    std::fs::remove_file(&trashed.info)
+}
+
+// $XDG_DATA_HOME, or its default.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn data_home() -> Result<PathBuf, Error> {
+    use std::env;
+
+    match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => Ok(PathBuf::from(dir)),
+        _ => env::var_os("HOME")
+            .map(|home| PathBuf::from(home).join(".local").join("share"))
+            .ok_or_else(|| Error::new(io::ErrorKind::NotFound, "HOME is not set")),
+    }
+}
+
+// The home trash when `path` is on its file system, `$topdir/.Trash-$uid`
+// otherwise so that the entry is renamed and not copied.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn trash_dir(path: &Path, device: u64, data: &Path) -> Result<PathBuf, Error> {
+    use std::{fs, os::unix::fs::MetadataExt};
+
+    fs::create_dir_all(data)?;
+    if fs::metadata(data)?.dev() == device {
+        return Ok(data.join("Trash"));
+    }
+
+    // That of the process, whoever owns the home directory.
+    let uid = unsafe { libc::getuid() };
+
+    let mut top = path;
+    while let Some(parent) = top.parent() {
+        if fs::symlink_metadata(parent)?.dev() != device {
+            break;
+        }
+        top = parent;
+    }
+    Ok(top.join(format!(".Trash-{}", uid)))
+}
+
+/// Percent-encoded, as in URLs.
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn encode_path(path: &Path) -> String {
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut encoded = String::new();
+    for &byte in path.as_os_str().as_bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded += &format!("%{:02X}", byte);
+        }
+    }
+    encoded
+}
+
+// YYYY-MM-DDThh:mm:ss, in UTC as there is no time zone database at hand.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn deletion_date(seconds: u64) -> String {
+    let (year, month, day) = crate::snapshot::date(seconds);
+    let time = seconds % (24 * 60 * 60);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+#[cfg(all(test, unix, not(target_os = "macos")))]
+mod tests {
+    use super::*;
+    use std::{fs, os::unix::fs::PermissionsExt};
+
+    #[test]
+    fn trashes_and_restores() {
+        let dir = std::env::temp_dir().join(format!("rustitude-trash-{}", std::process::id()));
+        let data = dir.join("data");
+        fs::create_dir_all(&data).unwrap();
+        let path = dir.join("a file");
+        fs::write(&path, "x").unwrap();
+
+        let first = trash_with(&path, &data).unwrap();
+        fs::write(&path, "y").unwrap();
+        let second = trash_with(&path, &data).unwrap();
+        assert!(!path.exists());
+        assert_eq!(second.file, data.join("Trash/files/a file.2"));
+        let mode = fs::metadata(data.join("Trash"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o700);
+        let info = fs::read_to_string(&first.info).unwrap();
+        assert!(info.contains("Path=") && info.contains("/a%20file\n"));
+
+        restore(&first).unwrap();
+        assert!(restore(&second).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "x");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
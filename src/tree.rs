@@ -0,0 +1,474 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    ops::{AddAssign, SubAssign},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+pub type NodeId = u32;
+type NameId = u32;
+
+const NONE: NodeId = NodeId::MAX;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Dir,
+    File,
+    /// Has children when the scanner followed it.
+    Symlink,
+    /// Root of another file system, has children once it has been scanned.
+    MountPoint,
+    /// Total of the paths left out by the exclude patterns, not on disk.
+    Excluded,
+}
+
+/// Which of the two sizes drives the chart and the reports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SizeMode {
+    /// The length of the files, what `ls -l` shows.
+    Apparent,
+    /// The blocks reserved on disk, what `du` shows.
+    Allocated,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Size {
+    pub apparent: u64,
+    pub allocated: u64,
+}
+
+impl Size {
+    pub fn get(&self, mode: SizeMode) -> u64 {
+        match mode {
+            SizeMode::Apparent => self.apparent,
+            SizeMode::Allocated => self.allocated,
+        }
+    }
+}
+
+impl AddAssign for Size {
+    fn add_assign(&mut self, other: Size) {
+        self.apparent += other.apparent;
+        self.allocated += other.allocated;
+    }
+}
+
+impl SubAssign for Size {
+    fn sub_assign(&mut self, other: Size) {
+        self.apparent -= other.apparent;
+        self.allocated -= other.allocated;
+    }
+}
+
+/// Files and directories below a node, see `Tree::counts`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub files: u32,
+    pub dirs: u32,
+}
+
+// Kept to 40 bytes so that a tree of 10M entries stays around 400 MB plus the
+// distinct names. Children form a singly linked list through `next_sibling`.
+struct Node {
+    parent: NodeId,
+    first_child: NodeId,
+    next_sibling: NodeId,
+    name: NameId,
+    size: Size,
+    mtime: u32, // seconds since the epoch, 0 when unknown
+    kind: Kind,
+}
+
+/// Every distinct file name is stored once, `node_modules` or `index.js`
+/// repeat a lot in the trees we are interested in.
+#[derive(Default)]
+struct Names {
+    ids: HashMap<Arc<OsStr>, NameId>,
+    names: Vec<Arc<OsStr>>,
+}
+
+impl Names {
+    fn intern(&mut self, name: &OsStr) -> NameId {
+        if let Some(id) = self.ids.get(name) {
+            return *id;
+        }
+        let id = self.names.len() as NameId;
+        let name: Arc<OsStr> = Arc::from(name);
+        self.names.push(name.clone());
+        self.ids.insert(name, id);
+        id
+    }
+
+    fn get(&self, id: NameId) -> &OsStr {
+        &self.names[id as usize]
+    }
+}
+
+/// Arena holding the scanned entries, built incrementally by the scanner.
+/// Nodes are addressed by `NodeId` and never move once added.
+pub struct Tree {
+    nodes: Vec<Node>,
+    names: Names,
+    atimes: Vec<u32>, // by id, out of `Node` to keep it small
+}
+
+impl Tree {
+    pub const ROOT: NodeId = 0;
+
+    /// Creates a tree holding only the directory `root`, whose name is the
+    /// full path so that `path()` of any node is absolute.
+    pub fn new(root: &Path) -> Self {
+        let mut tree = Tree {
+            nodes: Vec::new(),
+            names: Names::default(),
+            atimes: vec![0],
+        };
+        let name = tree.names.intern(root.as_os_str());
+        tree.nodes.push(Node {
+            parent: NONE,
+            first_child: NONE,
+            next_sibling: NONE,
+            name,
+            size: Size::default(),
+            mtime: 0,
+            kind: Kind::Dir,
+        });
+        tree
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Links a new node under `parent`. The size of the ancestors is left
+    /// untouched, see `grow`.
+    pub fn add(
+        &mut self,
+        parent: NodeId,
+        name: &OsStr,
+        kind: Kind,
+        size: Size,
+        mtime: u32,
+    ) -> NodeId {
+        let id = self.nodes.len() as NodeId;
+        let name = self.names.intern(name);
+        let next_sibling = self.nodes[parent as usize].first_child;
+        self.nodes.push(Node {
+            parent,
+            first_child: NONE,
+            next_sibling,
+            name,
+            size,
+            mtime,
+            kind,
+        });
+        self.nodes[parent as usize].first_child = id;
+        self.atimes.push(0);
+        id
+    }
+
+    /// Adds `size` to `id` and all of its ancestors.
+    pub fn grow(&mut self, id: NodeId, size: Size) {
+        let mut id = id;
+        while id != NONE {
+            let node = &mut self.nodes[id as usize];
+            node.size += size;
+            id = node.parent;
+        }
+    }
+
+    /// Unlinks `id` from its parent and takes its size off the ancestors, for
+    /// entries deleted from the disk. The node stays in the arena, see
+    /// `attach`. Returns the former parent.
+    pub fn detach(&mut self, id: NodeId) -> NodeId {
+        let node = &self.nodes[id as usize];
+        let (parent, next_sibling, size) = (node.parent, node.next_sibling, node.size);
+        assert!(parent != NONE, "the root cannot be detached");
+        if self.nodes[parent as usize].first_child == id {
+            self.nodes[parent as usize].first_child = next_sibling;
+        } else {
+            let previous = self
+                .children(parent)
+                .find(|child| self.nodes[*child as usize].next_sibling == id)
+                .unwrap();
+            self.nodes[previous as usize].next_sibling = next_sibling;
+        }
+        let node = &mut self.nodes[id as usize];
+        node.parent = NONE;
+        node.next_sibling = NONE;
+
+        let mut ancestor = parent;
+        while ancestor != NONE {
+            let node = &mut self.nodes[ancestor as usize];
+            node.size -= size;
+            ancestor = node.parent;
+        }
+        parent
+    }
+
+    /// Links a node taken out by `detach` back under `parent`.
+    pub fn attach(&mut self, parent: NodeId, id: NodeId) {
+        let next_sibling = self.nodes[parent as usize].first_child;
+        let node = &mut self.nodes[id as usize];
+        node.parent = parent;
+        node.next_sibling = next_sibling;
+        self.nodes[parent as usize].first_child = id;
+        self.grow(parent, self.nodes[id as usize].size);
+    }
+
+    /// Whether `id` is linked to the root, it is not below a detached node.
+    pub fn is_attached(&self, id: NodeId) -> bool {
+        let mut id = id;
+        while id != Tree::ROOT {
+            id = self.nodes[id as usize].parent;
+            if id == NONE {
+                return false;
+            }
+        }
+        true
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        match self.nodes[id as usize].parent {
+            NONE => None,
+            parent => Some(parent),
+        }
+    }
+
+    pub fn children(&self, id: NodeId) -> Children<'_> {
+        Children {
+            tree: self,
+            next: self.nodes[id as usize].first_child,
+        }
+    }
+
+    pub fn name(&self, id: NodeId) -> &OsStr {
+        self.names.get(self.nodes[id as usize].name)
+    }
+
+    pub fn size(&self, id: NodeId) -> Size {
+        self.nodes[id as usize].size
+    }
+
+    pub fn mtime(&self, id: NodeId) -> u32 {
+        self.nodes[id as usize].mtime
+    }
+
+    pub fn set_mtime(&mut self, id: NodeId, mtime: u32) {
+        self.nodes[id as usize].mtime = mtime;
+    }
+
+    /// Last access in seconds since the epoch, 0 when unknown.
+    pub fn atime(&self, id: NodeId) -> u32 {
+        self.atimes[id as usize]
+    }
+
+    pub fn set_atime(&mut self, id: NodeId, atime: u32) {
+        self.atimes[id as usize] = atime;
+    }
+
+    /// The latest of `time` of each node and everything below it, indexed by
+    /// id. Directories that hold anything only count their contents, reading
+    /// them for the scan touches their own times.
+    pub fn newest(&self, time: impl Fn(NodeId) -> u32) -> Vec<u32> {
+        let mut newest = vec![0; self.nodes.len()];
+        // Children always come after their parent.
+        for id in (0..self.nodes.len()).rev() {
+            let node = &self.nodes[id];
+            if node.first_child == NONE {
+                newest[id] = time(id as NodeId);
+            }
+            if node.parent != NONE {
+                let parent = node.parent as usize;
+                newest[parent] = newest[parent].max(newest[id]);
+            }
+        }
+        newest
+    }
+
+    pub fn kind(&self, id: NodeId) -> Kind {
+        self.nodes[id as usize].kind
+    }
+
+    /// How many files and directories are below each node, indexed by id.
+    /// Symlinks count as files, detached nodes are left out of their former
+    /// ancestors.
+    pub fn counts(&self) -> Vec<Counts> {
+        let mut counts = vec![Counts::default(); self.nodes.len()];
+        // Children always come after their parent.
+        for id in (1..self.nodes.len()).rev() {
+            let node = &self.nodes[id];
+            let mut count = counts[id];
+            match node.kind {
+                Kind::File | Kind::Symlink => count.files += 1,
+                Kind::Dir | Kind::MountPoint => count.dirs += 1,
+                Kind::Excluded => {}
+            }
+            if node.parent == NONE {
+                continue;
+            }
+            let parent = &mut counts[node.parent as usize];
+            parent.files += count.files;
+            parent.dirs += count.dirs;
+        }
+        counts
+    }
+
+    /// Drops the nodes left behind by `detach`, but those below `keep`, and
+    /// returns the new id of every old one. Children still come after their
+    /// parent.
+    pub fn compact(&mut self, keep: &[NodeId]) -> Vec<Option<NodeId>> {
+        let mut live = vec![false; self.nodes.len()];
+        live[Tree::ROOT as usize] = true;
+        for id in keep {
+            live[*id as usize] = true;
+        }
+        let mut ids = vec![None; self.nodes.len()];
+        let mut next = 0;
+        for id in 0..self.nodes.len() {
+            let parent = self.nodes[id].parent;
+            if parent != NONE && live[parent as usize] {
+                live[id] = true;
+            }
+            if live[id] {
+                ids[id] = Some(next);
+                next += 1;
+            }
+        }
+
+        let renumber = |id: NodeId| match id {
+            NONE => NONE,
+            id => ids[id as usize].unwrap(),
+        };
+        for (id, new) in ids.iter().enumerate() {
+            let new = match new {
+                Some(new) => *new as usize,
+                None => continue,
+            };
+            let node = &mut self.nodes[id];
+            node.parent = renumber(node.parent);
+            node.first_child = renumber(node.first_child);
+            node.next_sibling = renumber(node.next_sibling);
+            self.nodes.swap(new, id);
+            self.atimes.swap(new, id);
+        }
+        self.nodes.truncate(next as usize);
+        self.atimes.truncate(next as usize);
+        ids
+    }
+
+    pub fn path(&self, id: NodeId) -> PathBuf {
+        let mut names = Vec::new();
+        let mut id = id;
+        while id != NONE {
+            let node = &self.nodes[id as usize];
+            names.push(self.names.get(node.name));
+            id = node.parent;
+        }
+        names.iter().rev().collect()
+    }
+}
+
+pub struct Children<'a> {
+    tree: &'a Tree,
+    next: NodeId,
+}
+
+impl<'a> Iterator for Children<'a> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        if self.next == NONE {
+            return None;
+        }
+        let id = self.next;
+        self.next = self.tree.nodes[id as usize].next_sibling;
+        Some(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: Size = Size {
+        apparent: 5,
+        allocated: 8,
+    };
+
+    // /data/dir/old/file and /data/dir/kept, the ids of dir, old and kept.
+    fn sample() -> (Tree, NodeId, NodeId, NodeId) {
+        let mut tree = Tree::new(Path::new("/data"));
+        let dir = tree.add(Tree::ROOT, OsStr::new("dir"), Kind::Dir, Size::default(), 0);
+        let old = tree.add(dir, OsStr::new("old"), Kind::Dir, Size::default(), 0);
+        let file = tree.add(old, OsStr::new("file"), Kind::File, SIZE, 0);
+        tree.grow(file, SIZE);
+        let kept = tree.add(dir, OsStr::new("kept"), Kind::File, SIZE, 0);
+        tree.grow(dir, SIZE);
+        (tree, dir, old, kept)
+    }
+
+    #[test]
+    fn detaches_and_attaches() {
+        let (mut tree, dir, old, kept) = sample();
+        assert_eq!(tree.size(Tree::ROOT).allocated, 16);
+
+        assert_eq!(tree.detach(old), dir);
+        assert_eq!(tree.size(Tree::ROOT), SIZE);
+        assert_eq!(tree.size(old), SIZE);
+        assert_eq!(tree.children(dir).collect::<Vec<_>>(), vec![kept]);
+        assert!(!tree.is_attached(old));
+        assert!(!tree.is_attached(old + 1));
+        assert!(tree.is_attached(kept));
+        let counts = tree.counts();
+        assert_eq!(counts[Tree::ROOT as usize].files, 1);
+        assert_eq!(counts[Tree::ROOT as usize].dirs, 1);
+
+        tree.attach(dir, old);
+        assert_eq!(tree.size(Tree::ROOT).allocated, 16);
+        assert!(tree.is_attached(old + 1));
+        assert_eq!(tree.path(old + 1), Path::new("/data/dir/old/file"));
+        assert_eq!(tree.counts()[Tree::ROOT as usize].files, 2);
+    }
+
+    #[test]
+    fn compacts_detached_nodes() {
+        let (mut tree, dir, old, kept) = sample();
+        let trashed = tree.add(dir, OsStr::new("trashed"), Kind::Dir, Size::default(), 0);
+        let inner = tree.add(trashed, OsStr::new("inner"), Kind::File, SIZE, 0);
+        tree.grow(inner, SIZE);
+        tree.detach(old);
+        tree.detach(trashed);
+
+        let ids = tree.compact(&[trashed]);
+        assert_eq!(ids.len(), 7);
+        assert_eq!(ids[old as usize], None);
+        assert_eq!(ids[old as usize + 1], None);
+        assert_eq!(ids[dir as usize], Some(dir));
+        let (kept, trashed, inner) = (
+            ids[kept as usize].unwrap(),
+            ids[trashed as usize].unwrap(),
+            ids[inner as usize].unwrap(),
+        );
+        assert_eq!(tree.len(), 5);
+        assert_eq!(tree.children(dir).collect::<Vec<_>>(), vec![kept]);
+        assert_eq!(tree.path(kept), Path::new("/data/dir/kept"));
+        assert_eq!(tree.size(Tree::ROOT), SIZE);
+        assert!(!tree.is_attached(trashed));
+        assert_eq!(tree.children(trashed).collect::<Vec<_>>(), vec![inner]);
+        for id in 1..tree.len() as NodeId {
+            if let Some(parent) = tree.parent(id) {
+                assert!(parent < id);
+            }
+        }
+
+        // The kept nodes can still be put back.
+        tree.attach(dir, trashed);
+        assert_eq!(tree.path(inner), Path::new("/data/dir/trashed/inner"));
+        assert_eq!(tree.size(Tree::ROOT).allocated, 16);
+        assert_eq!(tree.counts()[Tree::ROOT as usize].files, 2);
+    }
+}